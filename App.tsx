@@ -1,17 +1,24 @@
 import React, { useState, createContext } from "react"
-import wasmInit, { Emulator } from "./core/pkg/gbemu_core"
+import wasmInit, { Emulator, Model, Renderer } from "./core/pkg/gbemu_core"
 import { Home } from "./Home"
 
 export const EmulatorContext = createContext<Emulator | null>(null)
+export const MemoryContext = createContext<WebAssembly.Memory | null>(null)
 
 export function App(): React.JSX.Element {
   const [emulator, setEmulator] = useState<Emulator | null>(null);
+  const [memory, setMemory] = useState<WebAssembly.Memory | null>(null);
   if (emulator == null) {
-    wasmInit().then(() => setEmulator(new Emulator()));
+    wasmInit().then((wasm) => {
+      setMemory(wasm.memory);
+      setEmulator(new Emulator(Model.Dmg, Renderer.Scanline));
+    });
   }
   return (
     <EmulatorContext.Provider value={emulator}>
-      <Home />
+      <MemoryContext.Provider value={memory}>
+        <Home />
+      </MemoryContext.Provider>
     </EmulatorContext.Provider>
   )
 }
@@ -1,24 +1,94 @@
-import React, { useContext } from "react";
-import { EmulatorContext } from "./App";
+import React, { useContext, useRef, useState } from "react";
+import { EmulatorContext, MemoryContext } from "./App";
+import { Scaler } from "./core/pkg/gbemu_core";
+
+const saveKey = (title: string) => `save:${title}`;
+
+const toBase64 = (data: Uint8Array) => {
+  let binary = "";
+  data.forEach((b) => binary += String.fromCharCode(b));
+  return btoa(binary);
+};
+
+const fromBase64 = (text: string) => Uint8Array.from(atob(text), (c) => c.charCodeAt(0));
+
+const scalers: [string, Scaler | null][] = [
+  ["Native", null],
+  ["Nearest 2x", Scaler.Nearest2x],
+  ["Nearest 3x", Scaler.Nearest3x],
+  ["Nearest 4x", Scaler.Nearest4x],
+  ["Scale2x", Scaler.Scale2x],
+  ["Scale3x", Scaler.Scale3x],
+  ["HQ2x", Scaler.Hq2x],
+  ["xBR 2x", Scaler.Xbr2x],
+];
 
 export function Home(): React.JSX.Element {
   const emulator = useContext(EmulatorContext);
+  const memory = useContext(MemoryContext);
+  const canvas = useRef<HTMLCanvasElement>(null);
+  // A ref rather than state, since the frame loop keeps the closure it started with.
+  const scaler = useRef<Scaler | null>(null);
+  const [status, setStatus] = useState("");
   const message = emulator == null ? "Emulator is not ready" : emulator.greet("wasmboy-rs");
   const nextFrame = () => {
     if (emulator == null) return;
     emulator.next_frame();
+    const context = canvas.current?.getContext("2d");
+    if (context != null && memory != null) {
+      // The view is recreated every frame since growing wasm memory detaches the old buffer.
+      let image: ImageData;
+      if (scaler.current == null) {
+        const pixels = new Uint8ClampedArray(memory.buffer, emulator.frame_ptr(), emulator.frame_len());
+        image = new ImageData(pixels, 160, 144);
+      } else {
+        emulator.upscale(scaler.current);
+        const pixels = new Uint8ClampedArray(memory.buffer, emulator.scaled_ptr(), emulator.scaled_len());
+        image = new ImageData(pixels, emulator.scaled_width(), emulator.scaled_height());
+      }
+      if (context.canvas.width != image.width) {
+        context.canvas.width = image.width;
+        context.canvas.height = image.height;
+      }
+      context.putImageData(image, 0, 0);
+    }
+    if (emulator.save_dirty()) {
+      const header = emulator.cartridge_header();
+      const save = emulator.export_save();
+      if (header != null && save != null) localStorage.setItem(saveKey(header.title), toBase64(save));
+    }
     requestAnimationFrame(nextFrame);
   };
   const handleChange = async (e: React.ChangeEvent<HTMLInputElement>) => {
     if (emulator == null || e.target.files == null) return;
     const file = e.target.files[0];
     const rom = await file.arrayBuffer().then((buf) => new Uint8Array(buf));
+    try {
+      emulator.load_rom(rom);
+    } catch (err) {
+      setStatus(`Failed to load ${file.name}: ${err}`);
+      return;
+    }
     emulator.init();
-    emulator.load_rom(rom);
+    const header = emulator.cartridge_header();
+    setStatus(header == null ? "" : `${header.title} (${header.cartridge_type_name})`);
+    const save = header?.has_battery ? localStorage.getItem(saveKey(header.title)) : null;
+    if (save != null) {
+      try {
+        emulator.import_save(fromBase64(save));
+      } catch (err) {
+        setStatus(`Failed to restore save: ${err}`);
+      }
+    }
     requestAnimationFrame(nextFrame);
   };
   return <>
     <h1>{message}</h1>
     <input type="file" onChange={handleChange} />
+    <select onChange={(e) => scaler.current = scalers[Number(e.target.value)][1]}>
+      {scalers.map(([name], i) => <option key={name} value={i}>{name}</option>)}
+    </select>
+    <canvas ref={canvas} width={160} height={144} />
+    <p>{status}</p>
   </>
 }
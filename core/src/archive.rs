@@ -0,0 +1,415 @@
+use crate::cartridge::MAX_ROM_SIZE;
+use std::borrow::Cow;
+use std::fmt;
+
+// ROMs distributed in ZIP or gzip containers, detected by their magic bytes.
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+// https://www.rfc-editor.org/rfc/rfc1952
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_LOCAL_HEADER: u32 = 0x04034b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+const GZIP_FHCRC: u8 = 1 << 1;
+const GZIP_FEXTRA: u8 = 1 << 2;
+const GZIP_FNAME: u8 = 1 << 3;
+const GZIP_FCOMMENT: u8 = 1 << 4;
+
+const ROM_EXTENSIONS: [&str; 4] = [".gb", ".gbc", ".cgb", ".sgb"];
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Corrupt,
+    UnsupportedCompression(u16),
+    TooLarge,
+    Checksum { expected: u32, actual: u32 },
+    NoRom,
+    MultipleRoms(Vec<String>),
+    EntryNotFound(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Corrupt => write!(f, "archive is corrupt"),
+            ArchiveError::UnsupportedCompression(method) => {
+                write!(f, "unsupported compression method {}", method)
+            }
+            ArchiveError::TooLarge => write!(f, "archive entry is too large to be a ROM"),
+            ArchiveError::Checksum { expected, actual } => write!(
+                f,
+                "archive entry is corrupt: CRC32 should be {:08x} but got {:08x}",
+                expected, actual
+            ),
+            ArchiveError::NoRom => write!(f, "archive does not contain a .gb or .gbc file"),
+            ArchiveError::MultipleRoms(names) => write!(
+                f,
+                "archive contains several ROMs, choose one of: {}",
+                names.join(", ")
+            ),
+            ArchiveError::EntryNotFound(name) => write!(f, "archive has no entry named {}", name),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+// Returns the ROM inside `data`, or `data` itself when it is not an archive. `entry` picks
+// a file by name from a ZIP archive instead of looking for the only ROM in it.
+pub fn extract_rom<'a>(data: &'a [u8], entry: Option<&str>) -> Result<Cow<'a, [u8]>, ArchiveError> {
+    if data.starts_with(GZIP_MAGIC) {
+        gunzip(data).map(Cow::Owned)
+    } else if data.len() >= 4 && u32_at(data, 0) == ZIP_LOCAL_HEADER {
+        unzip(data, entry).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(data))
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+// Anything that inflates beyond the largest ROM size is rejected.
+fn inflate(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_ROM_SIZE).map_err(|err| match err
+        .status
+    {
+        miniz_oxide::inflate::TINFLStatus::HasMoreOutput => ArchiveError::TooLarge,
+        _ => ArchiveError::Corrupt,
+    })
+}
+
+fn check_crc(data: &[u8], expected: u32) -> Result<(), ArchiveError> {
+    let actual = crc32fast::hash(data);
+    if actual != expected {
+        return Err(ArchiveError::Checksum { expected, actual });
+    }
+    Ok(())
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    // 10-byte header and 8-byte trailer (CRC32, size)
+    if data.len() < 18 {
+        return Err(ArchiveError::Corrupt);
+    }
+    let method = data[2] as u16;
+    if method != METHOD_DEFLATE {
+        return Err(ArchiveError::UnsupportedCompression(method));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & GZIP_FEXTRA != 0 {
+        pos += 2 + u16_at(data, pos) as usize;
+    }
+    for flag in [GZIP_FNAME, GZIP_FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data[pos.min(data.len())..]
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(ArchiveError::Corrupt)?;
+            pos += end + 1;
+        }
+    }
+    if flags & GZIP_FHCRC != 0 {
+        pos += 2;
+    }
+    let trailer = data.len() - 8;
+    if pos > trailer {
+        return Err(ArchiveError::Corrupt);
+    }
+
+    let rom = inflate(&data[pos..trailer])?;
+    check_crc(&rom, u32_at(data, trailer))?;
+    if rom.len() as u32 != u32_at(data, trailer + 4) {
+        return Err(ArchiveError::Corrupt);
+    }
+    Ok(rom)
+}
+
+struct ZipEntry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    local_header: usize,
+}
+
+fn zip_entries(data: &[u8]) -> Result<Vec<ZipEntry>, ArchiveError> {
+    // The end of central directory record is followed by a comment of up to 64 KiB.
+    let end = (0..=data.len().saturating_sub(ZIP_END_OF_CENTRAL_DIRECTORY_SIZE))
+        .rev()
+        .take(0x10000)
+        .find(|&i| u32_at(data, i) == ZIP_END_OF_CENTRAL_DIRECTORY)
+        .ok_or(ArchiveError::Corrupt)?;
+    let count = u16_at(data, end + 10) as usize;
+    let mut pos = u32_at(data, end + 16) as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if pos.saturating_add(46) > data.len() || u32_at(data, pos) != ZIP_CENTRAL_HEADER {
+            return Err(ArchiveError::Corrupt);
+        }
+        let name_len = u16_at(data, pos + 28) as usize;
+        let extra_len = u16_at(data, pos + 30) as usize;
+        let comment_len = u16_at(data, pos + 32) as usize;
+        let name = data
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or(ArchiveError::Corrupt)?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(data, pos + 10),
+            crc: u32_at(data, pos + 16),
+            compressed_size: u32_at(data, pos + 20) as usize,
+            size: u32_at(data, pos + 24) as usize,
+            local_header: u32_at(data, pos + 42) as usize,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    // Skip resource forks added by macOS.
+    !name.starts_with("__macosx/") && ROM_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+fn unzip(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    let entries = zip_entries(data)?;
+    let entry = match entry {
+        Some(name) => entries
+            .iter()
+            .find(|e| e.name == name)
+            .ok_or_else(|| ArchiveError::EntryNotFound(name.to_string()))?,
+        None => {
+            let roms: Vec<&ZipEntry> = entries.iter().filter(|e| is_rom_name(&e.name)).collect();
+            match roms[..] {
+                [] => return Err(ArchiveError::NoRom),
+                [rom] => rom,
+                _ => {
+                    let names = roms.iter().map(|e| e.name.clone()).collect();
+                    return Err(ArchiveError::MultipleRoms(names));
+                }
+            }
+        }
+    };
+
+    if entry.size > MAX_ROM_SIZE {
+        return Err(ArchiveError::TooLarge);
+    }
+    let header = entry.local_header;
+    if header.saturating_add(30) > data.len() || u32_at(data, header) != ZIP_LOCAL_HEADER {
+        return Err(ArchiveError::Corrupt);
+    }
+    let start =
+        header + 30 + u16_at(data, header + 26) as usize + u16_at(data, header + 28) as usize;
+    let compressed = data
+        .get(start..start.saturating_add(entry.compressed_size))
+        .ok_or(ArchiveError::Corrupt)?;
+    let rom = match entry.method {
+        METHOD_STORED => compressed.to_vec(),
+        METHOD_DEFLATE => inflate(compressed)?,
+        method => return Err(ArchiveError::UnsupportedCompression(method)),
+    };
+    if rom.len() != entry.size {
+        return Err(ArchiveError::Corrupt);
+    }
+    check_crc(&rom, entry.crc)?;
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec;
+
+    // Builds a ZIP archive of (name, method, contents) entries.
+    fn zip(files: &[(&str, u16, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central = Vec::new();
+        for &(name, method, contents) in files {
+            let compressed = match method {
+                METHOD_DEFLATE => compress_to_vec(contents, 6),
+                _ => contents.to_vec(),
+            };
+            let crc = crc32fast::hash(contents);
+            let offset = data.len() as u32;
+            data.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            data.extend_from_slice(&[20, 0, 0, 0]);
+            data.extend_from_slice(&method.to_le_bytes());
+            data.extend_from_slice(&[0; 4]);
+            data.extend_from_slice(&crc.to_le_bytes());
+            data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0; 2]);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&compressed);
+
+            central.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            central.extend_from_slice(&method.to_le_bytes());
+            central.extend_from_slice(&[0; 4]);
+            central.extend_from_slice(&crc.to_le_bytes());
+            central.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let central_offset = data.len() as u32;
+        data.extend_from_slice(&central);
+        data.extend_from_slice(&ZIP_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        data.extend_from_slice(&central_offset.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+        data
+    }
+
+    fn gzip(contents: &[u8], flags: u8) -> Vec<u8> {
+        let mut data = vec![0x1f, 0x8b, METHOD_DEFLATE as u8, flags, 0, 0, 0, 0, 0, 0xff];
+        if flags & GZIP_FEXTRA != 0 {
+            data.extend_from_slice(&[4, 0, b'a', b'b', 2, 0]);
+        }
+        if flags & GZIP_FNAME != 0 {
+            data.extend_from_slice(b"game.gb\0");
+        }
+        data.extend_from_slice(&compress_to_vec(contents, 6));
+        data.extend_from_slice(&crc32fast::hash(contents).to_le_bytes());
+        data.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        data
+    }
+
+    fn rom(seed: u8) -> Vec<u8> {
+        (0..0x8000u32).map(|i| (i % 251) as u8 ^ seed).collect()
+    }
+
+    #[test]
+    fn passes_plain_roms_through() {
+        let rom = rom(0);
+        assert!(matches!(extract_rom(&rom, None), Ok(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn unzips_stored_and_deflated_entries() {
+        let rom = rom(0);
+        for method in [METHOD_STORED, METHOD_DEFLATE] {
+            let data = zip(&[
+                ("readme.txt", METHOD_STORED, b"hi"),
+                ("game.gb", method, &rom),
+            ]);
+            assert_eq!(extract_rom(&data, None).unwrap(), &rom[..]);
+        }
+    }
+
+    #[test]
+    fn lists_the_roms_in_a_multi_rom_zip() {
+        let (a, b) = (rom(1), rom(2));
+        let data = zip(&[
+            ("a.gb", METHOD_DEFLATE, &a),
+            ("readme.txt", METHOD_STORED, b"hi"),
+            ("b.GBC", METHOD_STORED, &b),
+        ]);
+        match extract_rom(&data, None) {
+            Err(ArchiveError::MultipleRoms(names)) => assert_eq!(names, ["a.gb", "b.GBC"]),
+            other => panic!("unexpected result {:?}", other.map(|rom| rom.len())),
+        }
+    }
+
+    #[test]
+    fn picks_a_named_entry_among_several_roms() {
+        let (a, b) = (rom(1), rom(2));
+        let data = zip(&[("a.gb", METHOD_DEFLATE, &a), ("b.GBC", METHOD_STORED, &b)]);
+        assert_eq!(extract_rom(&data, Some("b.GBC")).unwrap(), &b[..]);
+        assert!(matches!(
+            extract_rom(&data, Some("c.gb")),
+            Err(ArchiveError::EntryNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn skips_resource_forks_and_other_files() {
+        let rom = rom(0);
+        let data = zip(&[
+            ("__MACOSX/._game.gb", METHOD_STORED, b"fork"),
+            ("game.gb", METHOD_STORED, &rom),
+        ]);
+        assert_eq!(extract_rom(&data, None).unwrap(), &rom[..]);
+        let data = zip(&[("readme.txt", METHOD_STORED, b"hi")]);
+        assert!(matches!(extract_rom(&data, None), Err(ArchiveError::NoRom)));
+    }
+
+    #[test]
+    fn checks_zip_entry_crcs() {
+        let rom = rom(0);
+        let mut data = zip(&[("game.gb", METHOD_STORED, &rom)]);
+        data[30 + "game.gb".len()] ^= 0xff;
+        assert!(matches!(
+            extract_rom(&data, None),
+            Err(ArchiveError::Checksum { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_truncated_central_directory() {
+        let (a, b) = (rom(1), rom(2));
+        let data = zip(&[("a.gb", METHOD_STORED, &a), ("b.gb", METHOD_STORED, &b)]);
+        // Drop the second central directory entry but keep the record count at two.
+        let end = data.len() - ZIP_END_OF_CENTRAL_DIRECTORY_SIZE;
+        let mut truncated = data[..end - (46 + "b.gb".len())].to_vec();
+        truncated.extend_from_slice(&data[end..]);
+        assert!(matches!(
+            extract_rom(&truncated, None),
+            Err(ArchiveError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_compression_methods() {
+        const METHOD_LZMA: u16 = 14;
+        let rom = rom(0);
+        let data = zip(&[("game.gb", METHOD_LZMA, &rom)]);
+        assert!(matches!(
+            extract_rom(&data, None),
+            Err(ArchiveError::UnsupportedCompression(METHOD_LZMA))
+        ));
+    }
+
+    #[test]
+    fn gunzips_with_optional_header_fields() {
+        let rom = rom(3);
+        for flags in [0, GZIP_FNAME, GZIP_FEXTRA, GZIP_FEXTRA | GZIP_FNAME] {
+            assert_eq!(extract_rom(&gzip(&rom, flags), None).unwrap(), &rom[..]);
+        }
+    }
+
+    #[test]
+    fn checks_the_gzip_trailer() {
+        let rom = rom(3);
+        let mut data = gzip(&rom, GZIP_FNAME);
+        let crc = data.len() - 8;
+        data[crc] ^= 0xff;
+        assert!(matches!(
+            extract_rom(&data, None),
+            Err(ArchiveError::Checksum { .. })
+        ));
+        assert!(matches!(
+            extract_rom(&data[..12], None),
+            Err(ArchiveError::Corrupt)
+        ));
+    }
+}
@@ -1,43 +1,99 @@
+use crate::cartridge::Cartridge;
 use crate::console_log;
 use crate::context::Context;
+use crate::dma::Dma;
+use crate::mbc::RomOnly;
+use crate::ppu::Ppu;
 use crate::timer::Timer;
 
+// https://gbdev.io/pandocs/Power_Up_Sequence.html
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
 pub struct Bus {
-    pub cart_rom: [u8; 32 * 1024],
-    pub cart_ram: [u8; 8 * 1024],
+    // Overlays the cartridge ROM until a write to 0xff50 unmaps it.
+    pub boot_rom: Option<Vec<u8>>,
+    pub cart: Box<dyn Cartridge>,
     pub work_ram: [u8; 8 * 1024],
     pub high_ram: [u8; 127],
     pub timer: Timer,
+    pub ppu: Ppu,
+    pub dma: Dma,
 }
 
 impl Bus {
     pub fn new() -> Bus {
         Bus {
-            cart_rom: [0; 32 * 1024],
-            cart_ram: [0; 8 * 1024],
+            boot_rom: None,
+            cart: Box::new(RomOnly::new(Vec::new(), 0)),
             work_ram: [0; 8 * 1024],
             high_ram: [0; 127],
             timer: Timer::default(),
+            ppu: Ppu::default(),
+            dma: Dma::default(),
         }
     }
 
     pub fn tick(&mut self, ctx: &mut Context) {
+        if let Some((addr, index)) = self.dma.tick() {
+            self.ppu.oam[index as usize] = self.dma_read(addr);
+        }
         // advance the processing of each component by 4 clock cycles.
         for _ in 0..4 {
             self.timer.tick(ctx);
+            self.cart.tick();
+            self.ppu.tick(ctx);
+        }
+    }
+
+    // Sources from 0xe000 up read the echo of work RAM. DMA reads do not count as CPU reads on
+    // carts that react to them.
+    fn dma_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7fff => self.cart.peek_rom(addr),
+            0x8000..=0x9fff => self.ppu.vram[addr as usize - 0x8000],
+            0xa000..=0xbfff => self.cart.peek_ram(addr),
+            0xc000..=0xdfff => self.work_ram[addr as usize - 0xc000],
+            _ => self.work_ram[addr as usize - 0xe000],
         }
     }
 
     pub fn read(&mut self, ctx: &Context, addr: u16) -> u8 {
+        if self.dma.is_active() && !(0xff80..=0xfffe).contains(&addr) {
+            return 0xff;
+        }
+        if let Some(boot_rom) = &self.boot_rom {
+            // The CGB boot ROM leaves a gap for the cartridge header at 0x0100-0x01ff.
+            let addr = addr as usize;
+            if addr < DMG_BOOT_ROM_SIZE || (0x200..CGB_BOOT_ROM_SIZE).contains(&addr) {
+                if let Some(&value) = boot_rom.get(addr) {
+                    return value;
+                }
+            }
+        }
         match addr {
-            0x0000..=0x7fff => self.cart_rom[addr as usize],
-            0xa000..=0xbfff => self.cart_ram[addr as usize - 0xa000],
+            0x0000..=0x7fff => self.cart.read_rom(addr),
+            0x8000..=0x9fff if self.ppu.vram_accessible() => self.ppu.vram[addr as usize - 0x8000],
+            0xa000..=0xbfff => self.cart.read_ram(addr),
             0xc000..=0xdfff => self.work_ram[addr as usize - 0xc000],
+            0xfe00..=0xfe9f if self.ppu.oam_accessible() => self.ppu.oam[addr as usize - 0xfe00],
             0xff04 => self.timer.div,
             0xff05 => self.timer.tima,
             0xff06 => self.timer.tma,
             0xff07 => self.timer.tac,
             0xff0f => ctx.interrupt_flag,
+            0xff40 => self.ppu.lcdc,
+            0xff41 => self.ppu.stat(),
+            0xff42 => self.ppu.scy,
+            0xff43 => self.ppu.scx,
+            0xff44 => self.ppu.ly(),
+            0xff45 => self.ppu.lyc(),
+            0xff46 => self.dma.source,
+            0xff47 => self.ppu.bgp,
+            0xff48 => self.ppu.obp0,
+            0xff49 => self.ppu.obp1,
+            0xff4a => self.ppu.wy,
+            0xff4b => self.ppu.wx,
             0xff80..=0xfffe => self.high_ram[addr as usize - 0xff80],
             0xffff => ctx.interrupt_enable,
             _ => 0xff,
@@ -45,18 +101,99 @@ impl Bus {
     }
 
     pub fn write(&mut self, ctx: &mut Context, addr: u16, value: u8) {
+        if self.dma.is_active() && !(0xff80..=0xfffe).contains(&addr) {
+            return;
+        }
         match addr {
-            0xa000..=0xbfff => self.cart_ram[addr as usize - 0xa000] = value,
+            0x0000..=0x7fff => self.cart.write_rom(addr, value),
+            0x8000..=0x9fff if self.ppu.vram_accessible() => {
+                self.ppu.vram[addr as usize - 0x8000] = value
+            }
+            0xa000..=0xbfff => self.cart.write_ram(addr, value),
             0xc000..=0xdfff => self.work_ram[addr as usize - 0xc000] = value,
+            0xfe00..=0xfe9f if self.ppu.oam_accessible() => {
+                self.ppu.oam[addr as usize - 0xfe00] = value
+            }
             0xff01 => console_log!("{}", value as char),
             0xff04 => self.timer.div = 0, // Writing any value to this register resets it to 0x00.
             0xff05 => self.timer.tima = value,
             0xff06 => self.timer.tma = value,
             0xff07 => self.timer.tac = value,
             0xff0f => ctx.interrupt_flag = value,
+            0xff40 => self.ppu.write_lcdc(ctx, value),
+            0xff41 => self.ppu.write_stat(ctx, value),
+            0xff42 => self.ppu.scy = value,
+            0xff43 => self.ppu.scx = value,
+            0xff45 => self.ppu.write_lyc(ctx, value),
+            0xff46 => self.dma.start(value),
+            0xff47 => self.ppu.bgp = value,
+            0xff48 => self.ppu.obp0 = value,
+            0xff49 => self.ppu.obp1 = value,
+            0xff4a => self.ppu.wy = value,
+            0xff4b => self.ppu.wx = value,
+            0xff50 if value != 0 => self.boot_rom = None,
             0xff80..=0xfffe => self.high_ram[addr as usize - 0xff80] = value,
             0xffff => ctx.interrupt_enable = value,
             _ => {}
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Starts a transfer from `source` and runs it to completion.
+    fn run_dma(bus: &mut Bus, ctx: &mut Context, source: u8) {
+        bus.write(ctx, 0xff46, source);
+        for _ in 0..162 {
+            bus.tick(ctx);
+        }
+        assert!(!bus.dma.is_active());
+    }
+
+    #[test]
+    fn copies_from_work_ram_and_its_echo() {
+        let mut bus = Bus::new();
+        let mut ctx = Context::default();
+        for (i, byte) in bus.work_ram.iter_mut().enumerate() {
+            *byte = i as u8 ^ (i >> 8) as u8;
+        }
+        run_dma(&mut bus, &mut ctx, 0xc1);
+        assert_eq!(bus.ppu.oam[..160], bus.work_ram[0x100..0x1a0]);
+        run_dma(&mut bus, &mut ctx, 0xe2);
+        assert_eq!(bus.ppu.oam[..160], bus.work_ram[0x200..0x2a0]);
+        // 0xfe00 and 0xff00 continue the echo instead of reading OAM and the I/O registers.
+        run_dma(&mut bus, &mut ctx, 0xfe);
+        assert_eq!(bus.ppu.oam[..160], bus.work_ram[0x1e00..0x1ea0]);
+        run_dma(&mut bus, &mut ctx, 0xff);
+        assert_eq!(bus.ppu.oam[..160], bus.work_ram[0x1f00..0x1fa0]);
+    }
+
+    #[test]
+    fn blocks_everything_but_hram_during_a_transfer() {
+        let mut bus = Bus::new();
+        let mut ctx = Context::default();
+        bus.write(&mut ctx, 0xc000, 0x12);
+        bus.write(&mut ctx, 0xff80, 0x34);
+        bus.write(&mut ctx, 0xff46, 0xc0);
+        // Nothing is blocked during the start delay.
+        bus.tick(&mut ctx);
+        assert_eq!(bus.read(&ctx, 0xc000), 0x12);
+        bus.tick(&mut ctx);
+        assert!(bus.dma.is_active());
+        assert_eq!(bus.read(&ctx, 0xc000), 0xff);
+        assert_eq!(bus.read(&ctx, 0xff46), 0xff);
+        bus.write(&mut ctx, 0xc000, 0x56);
+        bus.write(&mut ctx, 0xffff, 0x1f);
+        assert_eq!(bus.read(&ctx, 0xff80), 0x34);
+        bus.write(&mut ctx, 0xff80, 0x78);
+        for _ in 0..160 {
+            bus.tick(&mut ctx);
+        }
+        assert!(!bus.dma.is_active());
+        assert_eq!(bus.read(&ctx, 0xc000), 0x12);
+        assert_eq!(ctx.interrupt_enable, 0x00);
+        assert_eq!(bus.read(&ctx, 0xff80), 0x78);
+    }
+}
@@ -0,0 +1,573 @@
+use std::any::Any;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html
+const HEADER_END: usize = 0x150;
+const TITLE: usize = 0x134;
+const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE_CODE: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+const CARTRIDGE_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+const RAM_SIZE: usize = 0x149;
+const OLD_LICENSEE_CODE: usize = 0x14b;
+const VERSION: usize = 0x14c;
+const HEADER_CHECKSUM: usize = 0x14d;
+const GLOBAL_CHECKSUM: usize = 0x14e;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    MissingHeader { actual: usize },
+    Truncated { declared: usize, actual: usize },
+    Oversized { declared: usize, actual: usize },
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    HeaderChecksum { expected: u8, actual: u8 },
+    UnsupportedType(u8),
+    InvalidRtcState(usize),
+    NoBattery,
+    SaveSize { expected: usize, actual: usize },
+    InvalidState,
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::MissingHeader { actual } => write!(
+                f,
+                "ROM image is {} bytes, too short to contain a cartridge header",
+                actual
+            ),
+            CartridgeError::Truncated { declared, actual } => write!(
+                f,
+                "ROM image is truncated: header declares {} bytes but got {}",
+                declared, actual
+            ),
+            CartridgeError::Oversized { declared, actual } => write!(
+                f,
+                "ROM image is oversized: header declares {} bytes but got {}",
+                declared, actual
+            ),
+            CartridgeError::UnknownRomSize(code) => {
+                write!(f, "unknown ROM size code {:#04x}", code)
+            }
+            CartridgeError::UnknownRamSize(code) => {
+                write!(f, "unknown RAM size code {:#04x}", code)
+            }
+            CartridgeError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "header checksum mismatch: header says {:#04x} but computed {:#04x}",
+                expected, actual
+            ),
+            CartridgeError::UnsupportedType(code) => write!(
+                f,
+                "unsupported cartridge type {:#04x} ({})",
+                code,
+                cartridge_type_name(*code)
+            ),
+            CartridgeError::InvalidRtcState(len) => {
+                write!(f, "RTC state must be 44 or 48 bytes but got {}", len)
+            }
+            CartridgeError::NoBattery => write!(f, "cartridge has no battery-backed memory"),
+            CartridgeError::SaveSize { expected, actual } => {
+                write!(f, "save data must be {} bytes but got {}", expected, actual)
+            }
+            CartridgeError::InvalidState => {
+                write!(f, "cartridge state does not match this cartridge")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+// Everything the bus needs from a cartridge. The built-in mappers live in `mbc`; other
+// implementations can be inserted with `Emulator::load_cartridge`.
+pub trait Cartridge: Any {
+    // 0x0000-0x7fff. `peek_rom` must return what a read would without its side effects, for
+    // OAM DMA and debuggers; CPU reads go through `read_rom`.
+    fn peek_rom(&self, addr: u16) -> u8;
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        self.peek_rom(addr)
+    }
+    fn write_rom(&mut self, addr: u16, value: u8);
+
+    // 0xa000-0xbfff
+    fn peek_ram(&self, _addr: u16) -> u8 {
+        0xff
+    }
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        self.peek_ram(addr)
+    }
+    fn write_ram(&mut self, _addr: u16, _value: u8) {}
+
+    // Called on every clock cycle, four times per M-cycle.
+    fn tick(&mut self) {}
+
+    // Puts the cartridge in the state the boot ROM leaves it in, when none is run.
+    fn skip_boot(&mut self) {}
+
+    // The battery-backed memory, if any.
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // Whether the battery-backed memory has changed since the flag was last cleared. Register
+    // writes and writes that leave the memory as it was do not count.
+    fn save_dirty(&self) -> bool {
+        false
+    }
+    fn clear_save_dirty(&mut self) {}
+
+    // Battery-backed memory in the usual `.sav` layout.
+    fn save_battery(&mut self) -> Vec<u8> {
+        self.ram().to_vec()
+    }
+    fn load_battery(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        if data.len() != self.ram().len() {
+            return Err(CartridgeError::SaveSize {
+                expected: self.ram().len(),
+                actual: data.len(),
+            });
+        }
+        self.ram_mut().copy_from_slice(data);
+        Ok(())
+    }
+
+    // Registers and memory for save states; the ROM is not included.
+    fn save_state(&mut self) -> Vec<u8>;
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError>;
+}
+
+// Computes the checksum over 0x0134-0x014c that the header stores at 0x014d. `rom` must hold a
+// whole header.
+pub(crate) fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE..=VERSION]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+pub(crate) fn header_checksum_valid(rom: &[u8]) -> bool {
+    rom.len() >= HEADER_END && header_checksum(rom) == rom[HEADER_CHECKSUM]
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    title: String,
+    cgb_flag: u8,
+    sgb_flag: u8,
+    cartridge_type: u8,
+    rom_size: usize,
+    ram_size: usize,
+    licensee: String,
+    version: u8,
+    header_checksum: u8,
+    global_checksum: u16,
+    global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        let header = CartridgeHeader::read(rom)?;
+
+        let rom_size =
+            rom_size(rom[ROM_SIZE]).ok_or(CartridgeError::UnknownRomSize(rom[ROM_SIZE]))?;
+        ram_size(rom[RAM_SIZE]).ok_or(CartridgeError::UnknownRamSize(rom[RAM_SIZE]))?;
+        if rom.len() < rom_size {
+            return Err(CartridgeError::Truncated {
+                declared: rom_size,
+                actual: rom.len(),
+            });
+        }
+        if rom.len() > rom_size {
+            return Err(CartridgeError::Oversized {
+                declared: rom_size,
+                actual: rom.len(),
+            });
+        }
+
+        // The boot ROM refuses to start the cartridge if this checksum does not match.
+        let header_checksum = header_checksum(rom);
+        if header_checksum != header.header_checksum {
+            return Err(CartridgeError::HeaderChecksum {
+                expected: header.header_checksum,
+                actual: header_checksum,
+            });
+        }
+        Ok(header)
+    }
+
+    // Reads the header without checking it against the ROM, for carts whose headers lie.
+    // Unknown sizes are taken as the ROM's actual size and no RAM.
+    pub fn read(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::MissingHeader { actual: rom.len() });
+        }
+
+        // The global checksum is not verified by the hardware, so a mismatch is only reported.
+        let global_checksum = (rom[GLOBAL_CHECKSUM] as u16) << 8 | rom[GLOBAL_CHECKSUM + 1] as u16;
+        let sum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != GLOBAL_CHECKSUM && i != GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |x, (_, &b)| x.wrapping_add(b as u16));
+
+        // In CGB cartridges the last byte of the title area is the CGB flag.
+        let cgb_flag = rom[CGB_FLAG];
+        let title_end = if cgb_flag & 0x80 != 0 {
+            CGB_FLAG
+        } else {
+            CGB_FLAG + 1
+        };
+        let title = rom[TITLE..title_end]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '?'
+                }
+            })
+            .collect::<String>();
+
+        // 0x33 in the old licensee code means the new two-character code is used instead.
+        let licensee = if rom[OLD_LICENSEE_CODE] == 0x33 {
+            String::from_utf8_lossy(&rom[NEW_LICENSEE_CODE..NEW_LICENSEE_CODE + 2]).into_owned()
+        } else {
+            format!("{:02X}", rom[OLD_LICENSEE_CODE])
+        };
+
+        Ok(CartridgeHeader {
+            title,
+            cgb_flag,
+            sgb_flag: rom[SGB_FLAG],
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size: rom_size(rom[ROM_SIZE]).unwrap_or(rom.len()),
+            ram_size: ram_size(rom[RAM_SIZE]).unwrap_or(0),
+            licensee,
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum,
+            global_checksum_valid: sum == global_checksum,
+        })
+    }
+}
+
+#[wasm_bindgen]
+impl CartridgeHeader {
+    #[wasm_bindgen(getter)]
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cgb_flag(&self) -> u8 {
+        self.cgb_flag
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cgb_only(&self) -> bool {
+        self.cgb_flag == 0xc0
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sgb_flag(&self) -> u8 {
+        self.sgb_flag
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cartridge_type(&self) -> u8 {
+        self.cartridge_type
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cartridge_type_name(&self) -> String {
+        cartridge_type_name(self.cartridge_type).to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06
+                | 0x09
+                | 0x0d
+                | 0x0f
+                | 0x10
+                | 0x13
+                | 0x1b
+                | 0x1e
+                | 0x22
+                | 0xfc
+                | 0xfe
+                | 0xff
+        )
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn rom_size(&self) -> usize {
+        self.rom_size
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn ram_size(&self) -> usize {
+        self.ram_size
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn licensee(&self) -> String {
+        self.licensee.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn header_checksum(&self) -> u8 {
+        self.header_checksum
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn global_checksum(&self) -> u16 {
+        self.global_checksum
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum_valid
+    }
+}
+
+// The largest ROM size a cartridge header can declare.
+pub const MAX_ROM_SIZE: usize = 8 * 1024 * 1024;
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0148--rom-size
+fn rom_size(code: u8) -> Option<usize> {
+    match code {
+        0x00..=0x08 => Some((32 * 1024) << code),
+        // These sizes only appear in unofficial documentation.
+        0x52 => Some(72 * 16 * 1024),
+        0x53 => Some(80 * 16 * 1024),
+        0x54 => Some(96 * 16 * 1024),
+        _ => None,
+    }
+}
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0149--ram-size
+fn ram_size(code: u8) -> Option<usize> {
+    match code {
+        0x00 => Some(0),
+        0x01 => Some(2 * 1024),
+        0x02 => Some(8 * 1024),
+        0x03 => Some(32 * 1024),
+        0x04 => Some(128 * 1024),
+        0x05 => Some(64 * 1024),
+        _ => None,
+    }
+}
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0147--cartridge-type
+fn cartridge_type_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0b => "MMM01",
+        0x0c => "MMM01+RAM",
+        0x0d => "MMM01+RAM+BATTERY",
+        0x0f => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1a => "MBC5+RAM",
+        0x1b => "MBC5+RAM+BATTERY",
+        0x1c => "MBC5+RUMBLE",
+        0x1d => "MBC5+RUMBLE+RAM",
+        0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xfc => "POCKET CAMERA",
+        0xfd => "BANDAI TAMA5",
+        0xfe => "HuC3",
+        0xff => "HuC1+RAM+BATTERY",
+        _ => "UNKNOWN",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::infrared::Disconnected;
+    use crate::mbc;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A ROM image of the size the header declares, with a valid header checksum.
+    fn rom(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8) -> Vec<u8> {
+        let mut rom = vec![0; rom_size(rom_size_code).unwrap_or(0x8000)];
+        rom[TITLE..TITLE + 4].copy_from_slice(b"TEST");
+        rom[CARTRIDGE_TYPE] = cartridge_type;
+        rom[ROM_SIZE] = rom_size_code;
+        rom[RAM_SIZE] = ram_size_code;
+        rom[HEADER_CHECKSUM] = header_checksum(&rom);
+        rom
+    }
+
+    fn load(rom: Vec<u8>) -> Result<Box<dyn Cartridge>, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let infrared = Rc::new(RefCell::new(Box::new(Disconnected) as Box<_>));
+        mbc::from_header(&header, rom, Rc::new(SystemClock), infrared)
+    }
+
+    // MBC3 with a clock, 8 KiB of RAM and a battery.
+    fn mbc3() -> Box<dyn Cartridge> {
+        load(rom(0x10, 0x00, 0x02)).unwrap()
+    }
+
+    #[test]
+    fn accepts_a_valid_image() {
+        let header = CartridgeHeader::parse(&rom(0x01, 0x01, 0x00)).unwrap();
+        assert_eq!(header.title(), "TEST");
+        assert_eq!(header.rom_size(), 0x10000);
+    }
+
+    #[test]
+    fn rejects_images_without_a_header() {
+        let rom = vec![0; HEADER_END - 1];
+        assert!(matches!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::MissingHeader { actual: 0x14f })
+        ));
+    }
+
+    #[test]
+    fn rejects_images_shorter_than_declared() {
+        let mut rom = rom(0x01, 0x02, 0x00);
+        rom.truncate(0x10000);
+        assert!(matches!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::Truncated {
+                declared: 0x20000,
+                actual: 0x10000
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_images_longer_than_declared() {
+        let mut rom = rom(0x00, 0x00, 0x00);
+        rom.push(0);
+        assert!(matches!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::Oversized {
+                declared: 0x8000,
+                actual: 0x8001
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_size_codes() {
+        assert!(matches!(
+            CartridgeHeader::parse(&rom(0x00, 0x09, 0x00)),
+            Err(CartridgeError::UnknownRomSize(0x09))
+        ));
+        assert!(matches!(
+            CartridgeHeader::parse(&rom(0x00, 0x00, 0x06)),
+            Err(CartridgeError::UnknownRamSize(0x06))
+        ));
+    }
+
+    #[test]
+    fn rejects_a_header_checksum_mismatch() {
+        let mut rom = rom(0x00, 0x00, 0x00);
+        rom[HEADER_CHECKSUM] = 0x12;
+        let actual = header_checksum(&rom);
+        assert_ne!(actual, 0x12);
+        assert!(matches!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::HeaderChecksum { expected: 0x12, actual: a }) if a == actual
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_cartridge_types() {
+        assert!(matches!(
+            load(rom(0x20, 0x00, 0x00)),
+            Err(CartridgeError::UnsupportedType(0x20))
+        ));
+    }
+
+    #[test]
+    fn rejects_saves_of_the_wrong_size() {
+        let mut cart = mbc3();
+        assert!(matches!(
+            cart.load_battery(&[0; 0x1000]),
+            Err(CartridgeError::SaveSize {
+                expected: 0x2000,
+                actual: 0x1000
+            })
+        ));
+        let mut cart = load(rom(0x03, 0x00, 0x02)).unwrap();
+        assert!(matches!(
+            cart.load_battery(&[0; 0x2030]),
+            Err(CartridgeError::SaveSize {
+                expected: 0x2000,
+                actual: 0x2030
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_rtc_footers_of_the_wrong_size() {
+        let mut cart = mbc3();
+        assert!(matches!(
+            cart.load_battery(&[0; 0x2000 + 40]),
+            Err(CartridgeError::InvalidRtcState(40))
+        ));
+        assert!(cart.load_battery(&[0; 0x2000 + 44]).is_ok());
+        assert!(cart.load_battery(&[0; 0x2000 + 48]).is_ok());
+    }
+
+    #[test]
+    fn rejects_states_from_another_cartridge() {
+        let state = mbc3().save_state();
+        let mut cart = mbc3();
+        assert!(cart.load_state(&state).is_ok());
+        assert!(matches!(
+            cart.load_state(&state[..state.len() - 1]),
+            Err(CartridgeError::InvalidState)
+        ));
+        let mut cart = load(rom(0x03, 0x00, 0x03)).unwrap();
+        assert!(matches!(
+            cart.load_state(&state),
+            Err(CartridgeError::InvalidState)
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+use std::cell::Cell;
+use std::rc::Rc;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+// Time source for cartridges with a real-time clock.
+pub trait Clock {
+    // Returns the current time in seconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn date_now() -> f64;
+}
+
+// Wall-clock time, taken from `Date.now()` in the browser.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(target_arch = "wasm32")]
+    fn now(&self) -> u64 {
+        (date_now() / 1000.0) as u64
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+// A clock that only moves when told to, so time can be advanced deterministically.
+// Clones share the same time.
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    seconds: Rc<Cell<u64>>,
+}
+
+impl ManualClock {
+    pub fn new(seconds: u64) -> ManualClock {
+        ManualClock {
+            seconds: Rc::new(Cell::new(seconds)),
+        }
+    }
+
+    pub fn set(&self, seconds: u64) {
+        self.seconds.set(seconds);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.seconds.set(self.seconds.get() + seconds);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.seconds.get()
+    }
+}
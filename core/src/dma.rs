@@ -0,0 +1,72 @@
+// https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+// A write to 0xff46 starts copying 160 bytes from 0xXX00 to OAM, one per M-cycle, after a
+// one M-cycle delay. Writing again restarts the transfer; the old one keeps running during
+// the new one's delay.
+#[derive(Default)]
+pub struct Dma {
+    pub source: u8,
+    requested: Option<u16>,
+    starting: Option<u16>,
+    transfer: Option<(u16, u8)>, // source address and index of the byte copied this M-cycle
+}
+
+impl Dma {
+    pub fn start(&mut self, value: u8) {
+        self.source = value;
+        self.requested = Some((value as u16) << 8);
+    }
+
+    // Advances by one M-cycle. Returns the source address and OAM index of the byte to copy.
+    pub fn tick(&mut self) -> Option<(u16, u8)> {
+        self.transfer = match self.transfer {
+            Some((source, index)) if index < 159 => Some((source, index + 1)),
+            _ => None,
+        };
+        if let Some(source) = self.starting.take() {
+            self.transfer = Some((source, 0));
+        }
+        self.starting = self.requested.take();
+        self.transfer
+            .map(|(source, index)| (source + index as u16, index))
+    }
+
+    // While a transfer runs, the CPU can only use HRAM.
+    pub fn is_active(&self) -> bool {
+        self.transfer.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_160_bytes_after_a_one_cycle_delay() {
+        let mut dma = Dma::default();
+        dma.start(0xc0);
+        assert_eq!(dma.tick(), None);
+        assert!(!dma.is_active());
+        for index in 0..160 {
+            assert_eq!(dma.tick(), Some((0xc000 + index as u16, index)));
+            assert!(dma.is_active());
+        }
+        assert_eq!(dma.tick(), None);
+        assert!(!dma.is_active());
+    }
+
+    #[test]
+    fn restarting_keeps_the_old_transfer_through_the_delay() {
+        let mut dma = Dma::default();
+        dma.start(0xc0);
+        for _ in 0..=10 {
+            dma.tick();
+        }
+        dma.start(0xd0);
+        assert_eq!(dma.source, 0xd0);
+        assert_eq!(dma.tick(), Some((0xc00a, 10)));
+        for index in 0..160 {
+            assert_eq!(dma.tick(), Some((0xd000 + index as u16, index)));
+        }
+        assert_eq!(dma.tick(), None);
+    }
+}
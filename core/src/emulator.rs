@@ -1,50 +1,328 @@
 extern crate console_error_panic_hook;
+use crate::archive;
+use crate::bus::{CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+use crate::cartridge::{Cartridge, CartridgeError, CartridgeHeader};
+use crate::clock::{Clock, SystemClock};
 use crate::console_log;
-use crate::cpu::CPU;
+use crate::cpu::{Registers, CPU};
+use crate::dma::Dma;
+use crate::infrared::{Disconnected, Infrared, InfraredPort};
 use crate::inst;
+use crate::mbc::{self, Camera, Huc3, MapperKind, Mbc3, Mbc5, Mbc7, CAMERA_HEIGHT, CAMERA_WIDTH};
+use crate::model::Model;
+use crate::patch::{self, PatchError};
+use crate::ppu::{Ppu, Renderer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::scale::Scaler;
+use crate::screen::{ColorCorrection, PalettePreset, Screen};
+use crate::timer::Timer;
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub struct Emulator {
+    model: Model,
+    renderer: Renderer,
     cpu: CPU,
     clocks: isize,
+    header: Option<CartridgeHeader>,
+    clock: Rc<dyn Clock>,
+    infrared: Rc<RefCell<Box<dyn Infrared>>>,
+    patches: Vec<Vec<u8>>,
+    boot_rom: Option<Vec<u8>>,
+    forced_mapper: Option<MapperKind>,
+    screen: Screen,
 }
 
 #[wasm_bindgen]
 impl Emulator {
     #[wasm_bindgen(constructor)]
-    pub fn new() -> Emulator {
+    pub fn new(model: Model, renderer: Renderer) -> Emulator {
         Emulator {
+            model,
+            renderer,
             cpu: CPU::new(),
             clocks: 0,
+            header: None,
+            clock: Rc::new(SystemClock),
+            infrared: Rc::new(RefCell::new(Box::new(Disconnected))),
+            patches: Vec::new(),
+            boot_rom: None,
+            forced_mapper: None,
+            screen: Screen::default(),
         }
     }
 
+    // Call after `load_rom`, since the power-up state depends on the cartridge header.
     pub fn init(&mut self) {
-        // https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
-        self.cpu.registers.a = 0x01;
-        self.cpu.registers.b = 0x00;
-        self.cpu.registers.c = 0x13;
-        self.cpu.registers.d = 0x00;
-        self.cpu.registers.e = 0xd8;
-        self.cpu.registers.f = 0x80;
-        self.cpu.registers.h = 0x01;
-        self.cpu.registers.l = 0x4d;
-        self.cpu.registers.pc = 0x100;
-        self.cpu.registers.sp = 0xfffe;
-
-        self.cpu.bus.timer.div = 0xab;
-        self.cpu.bus.timer.tima = 0x00;
-        self.cpu.bus.timer.tma = 0x00;
+        if let Some(boot_rom) = &self.boot_rom {
+            // Everything the boot ROM sets up comes from running it.
+            self.cpu.bus.boot_rom = Some(boot_rom.clone());
+            self.cpu.registers = Registers::default();
+            self.cpu.bus.timer = Timer::default();
+            self.cpu.bus.ppu = Ppu::new(self.model, self.renderer);
+            self.cpu.bus.dma = Dma::default();
+            self.cpu.ctx.interrupt_flag = 0xe0;
+            self.cpu.ctx.interrupt_enable = 0x00;
+            return;
+        }
+
+        // Without a boot ROM, start from the state it leaves behind.
+        self.cpu.bus.boot_rom = None;
+        self.cpu.bus.cart.skip_boot();
+        let header_checksum = self.header.as_ref().map_or(0, |h| h.header_checksum());
+        let cgb_cartridge = self.header.as_ref().is_some_and(|h| h.supports_cgb());
+        self.cpu.registers = self.model.registers(header_checksum, cgb_cartridge);
+
+        // https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+        let divider = self.model.divider();
+        self.cpu.bus.timer = Timer::default();
+        self.cpu.bus.timer.div = (divider >> 8) as u8;
+        self.cpu.bus.timer.divider_counter = (divider & 0xff) as usize;
         self.cpu.bus.timer.tac = 0xf8;
+        self.cpu.bus.ppu = Ppu::after_boot(self.model, self.renderer);
+        self.cpu.bus.dma = Dma::default();
 
         self.cpu.ctx.interrupt_flag = 0xe1;
         self.cpu.ctx.interrupt_enable = 0x00;
     }
 
-    pub fn load_rom(&mut self, rom_data: &[u8]) {
-        let n = rom_data.len();
-        self.cpu.bus.cart_rom[0..n].copy_from_slice(rom_data);
+    // Supplies a 256-byte DMG/MGB or 2304-byte CGB boot ROM, run by the next `init`.
+    pub fn load_boot_rom(&mut self, data: &[u8]) -> Result<(), JsError> {
+        if data.len() != DMG_BOOT_ROM_SIZE && data.len() != CGB_BOOT_ROM_SIZE {
+            return Err(JsError::new(&format!(
+                "boot ROM must be {} or {} bytes but got {}",
+                DMG_BOOT_ROM_SIZE,
+                CGB_BOOT_ROM_SIZE,
+                data.len()
+            )));
+        }
+        self.boot_rom = Some(data.to_vec());
+        Ok(())
+    }
+
+    pub fn clear_boot_rom(&mut self) {
+        self.boot_rom = None;
+    }
+
+    // Queues an IPS, UPS or BPS patch. Queued patches are applied in order by every
+    // `load_rom` until `clear_patches` is called.
+    pub fn add_patch(&mut self, patch_data: &[u8]) -> Result<(), JsError> {
+        if !patch::is_patch(patch_data) {
+            return Err(PatchError::UnknownFormat.into());
+        }
+        self.patches.push(patch_data.to_vec());
+        Ok(())
+    }
+
+    pub fn clear_patches(&mut self) {
+        self.patches.clear();
+    }
+
+    // Uses the given mapper instead of detecting it, for carts the heuristics get wrong.
+    // Takes effect on the next `load_rom`.
+    pub fn force_mapper(&mut self, kind: MapperKind) {
+        self.forced_mapper = Some(kind);
+    }
+
+    pub fn clear_forced_mapper(&mut self) {
+        self.forced_mapper = None;
+    }
+
+    // Accepts a raw ROM or a ZIP/gzip archive holding exactly one .gb/.gbc file.
+    pub fn load_rom(&mut self, rom_data: &[u8]) -> Result<(), JsError> {
+        self.load(rom_data, None)
+    }
+
+    // Loads the named entry of a ZIP archive, for archives holding several ROMs.
+    pub fn load_rom_from_archive(&mut self, archive: &[u8], entry: &str) -> Result<(), JsError> {
+        self.load(archive, Some(entry))
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn cartridge_header(&self) -> Option<CartridgeHeader> {
+        self.header.clone()
+    }
+
+    fn has_battery(&self) -> bool {
+        self.header
+            .as_ref()
+            .is_some_and(|header| header.has_battery())
+    }
+
+    // Returns the battery-backed memory in the usual `.sav` layout: the cartridge RAM, followed
+    // by the RTC footer on MBC3 cartridges with a clock. Clears the dirty flag.
+    pub fn export_save(&mut self) -> Option<Vec<u8>> {
+        if !self.has_battery() {
+            return None;
+        }
+        let save = self.cpu.bus.cart.save_battery();
+        self.cpu.bus.cart.clear_save_dirty();
+        Some(save)
+    }
+
+    // Accepts saves with or without the RTC footer, since not every emulator writes one.
+    pub fn import_save(&mut self, data: &[u8]) -> Result<(), JsError> {
+        Ok(self.load_battery(data)?)
+    }
+
+    // Returns whether the battery-backed memory has changed since the last export or import.
+    pub fn save_dirty(&self) -> bool {
+        self.has_battery() && self.cpu.bus.cart.save_dirty()
+    }
+
+    // Returns the cartridge's RTC state in the 48-byte footer format used by `.sav` files.
+    pub fn rtc_state(&mut self) -> Option<Vec<u8>> {
+        self.cart_mut::<Mbc3>()
+            .and_then(|cart| cart.rtc())
+            .map(|rtc| rtc.save())
+    }
+
+    pub fn load_rtc_state(&mut self, data: &[u8]) -> Result<(), JsError> {
+        if let Some(rtc) = self.cart_mut::<Mbc3>().and_then(|cart| cart.rtc()) {
+            rtc.load(data)?;
+        }
+        Ok(())
+    }
+
+    // Returns whether the rumble motor has been on since the last call, for driving gamepad vibration.
+    pub fn rumble(&mut self) -> bool {
+        self.cart_mut::<Mbc5>()
+            .is_some_and(|cart| cart.take_rumble())
+    }
+
+    // Feeds the MBC7 accelerometer, in units of g. Has no effect on other cartridges.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Some(cart) = self.cart_mut::<Mbc7>() {
+            cart.set_tilt(x, y);
+        }
+    }
+
+    // Connects the IR ports of two emulators so each one sees the other's LED.
+    pub fn link_infrared(&mut self, other: &mut Emulator) {
+        let (port, other_port) = InfraredPort::pair();
+        self.connect_infrared(Box::new(port));
+        other.connect_infrared(Box::new(other_port));
+    }
+
+    pub fn disconnect_infrared(&mut self) {
+        self.connect_infrared(Box::new(Disconnected));
+    }
+
+    // Returns the tone last requested from the HuC3 tone generator, if any.
+    pub fn take_tone(&mut self) -> Option<u8> {
+        self.cart_mut::<Huc3>().and_then(|cart| cart.take_tone())
+    }
+
+    // Supplies the Pocket Camera sensor image: 128x112 grayscale pixels, 0 = black.
+    pub fn set_camera_image(&mut self, pixels: &[u8]) -> Result<(), JsError> {
+        if pixels.len() != CAMERA_WIDTH * CAMERA_HEIGHT {
+            return Err(JsError::new(&format!(
+                "camera image must be {}x{} pixels but got {} bytes",
+                CAMERA_WIDTH,
+                CAMERA_HEIGHT,
+                pixels.len()
+            )));
+        }
+        if let Some(cart) = self.cart_mut::<Camera>() {
+            cart.set_image(pixels);
+        }
+        Ok(())
+    }
+
+    // Returns the photo in album slot 0-29 as a PNG, or nothing if the slot is empty.
+    pub fn camera_photo_png(&self, slot: usize) -> Option<Vec<u8>> {
+        self.cart::<Camera>().and_then(|cart| cart.photo_png(slot))
+    }
+
+    // The last complete frame as 160x144 RGBA pixels in wasm memory. The pointer does not change,
+    // but views over it are invalidated whenever wasm memory grows.
+    pub fn frame_ptr(&self) -> *const u8 {
+        self.screen.frame.as_ptr()
+    }
+
+    pub fn frame_len(&self) -> usize {
+        self.screen.frame.len()
+    }
+
+    // Enlarges the frame on screen into a separate RGBA buffer of `scaled_width` x
+    // `scaled_height` pixels, leaving `frame_ptr` untouched. The scaler can change from one frame
+    // to the next, but the buffer moves when the scale factor does, so take `scaled_ptr` after
+    // each call.
+    pub fn upscale(&mut self, scaler: Scaler) {
+        self.screen.upscale(scaler);
+    }
+
+    pub fn scaled_ptr(&self) -> *const u8 {
+        self.screen.scaled.as_ptr()
+    }
+
+    pub fn scaled_len(&self) -> usize {
+        self.screen.scaled.len()
+    }
+
+    pub fn scaled_width(&self) -> usize {
+        SCREEN_WIDTH * self.screen.scale
+    }
+
+    pub fn scaled_height(&self) -> usize {
+        SCREEN_HEIGHT * self.screen.scale
+    }
+
+    // Maps the four DMG shades to RGB, for the background and both object palettes. Palette
+    // and color correction changes also apply to the frame already on screen.
+    pub fn set_palette(&mut self, preset: PalettePreset) {
+        self.screen.set_preset(preset);
+        self.screen.refresh(&self.cpu.bus.ppu.frame);
+    }
+
+    // Takes four 0xRRGGBB colors, lightest shade first, for each of BGP, OBP0 and OBP1, the
+    // way the CGB boot ROM colors DMG games.
+    pub fn set_custom_palette(
+        &mut self,
+        bg: &[u32],
+        obj0: &[u32],
+        obj1: &[u32],
+    ) -> Result<(), JsError> {
+        let palette = |colors: &[u32]| {
+            <[u32; 4]>::try_from(colors).map_err(|_| {
+                JsError::new(&format!(
+                    "palette must have 4 colors but got {}",
+                    colors.len()
+                ))
+            })
+        };
+        self.screen
+            .set_palettes([palette(bg)?, palette(obj0)?, palette(obj1)?]);
+        self.screen.refresh(&self.cpu.bus.ppu.frame);
+        Ok(())
+    }
+
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.screen.set_color_correction(correction);
+        self.screen.refresh(&self.cpu.bus.ppu.frame);
+    }
+
+    // Blends each frame into the previous ones to mimic the ghosting of the DMG screen, which
+    // flicker-based effects rely on. `decay` is the share of the old color kept each frame,
+    // from 0 (off) up to but not including 1.
+    pub fn set_frame_blend(&mut self, decay: f32) -> Result<(), JsError> {
+        if !(0.0..1.0).contains(&decay) {
+            return Err(JsError::new(&format!(
+                "frame blend decay must be at least 0 and below 1 but got {}",
+                decay
+            )));
+        }
+        self.screen.set_decay(decay);
+        Ok(())
     }
 
     pub fn next_frame(&mut self) {
@@ -52,14 +330,14 @@ impl Emulator {
         self.clocks += 17556;
         self.cpu.tick_count = 0;
         while self.cpu.tick_count < self.clocks {
-            self.cpu.execute();
+            self.execute();
         }
         self.clocks -= self.cpu.tick_count;
     }
 
     pub fn step_execute(&mut self, steps: usize) {
         for _ in 0..steps {
-            self.cpu.execute();
+            self.execute();
         }
     }
 
@@ -81,3 +359,111 @@ impl Emulator {
         }
     }
 }
+
+impl Default for Emulator {
+    fn default() -> Emulator {
+        Emulator::new(Model::default(), Renderer::default())
+    }
+}
+
+impl Emulator {
+    // Replaces the time source used by real-time clock cartridges. Takes effect on the next `load_rom`.
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // Puts an endpoint in front of the cartridge's IR port. Takes effect immediately.
+    pub fn connect_infrared(&mut self, endpoint: Box<dyn Infrared>) {
+        *self.infrared.borrow_mut() = endpoint;
+    }
+
+    fn execute(&mut self) {
+        self.cpu.execute();
+        if self.cpu.bus.ppu.take_frame() {
+            self.screen.present(&self.cpu.bus.ppu.frame);
+        }
+    }
+
+    // Archives are unpacked before queued patches are applied.
+    fn load(&mut self, data: &[u8], entry: Option<&str>) -> Result<(), JsError> {
+        let mut rom = archive::extract_rom(data, entry)?.into_owned();
+        for patch_data in &self.patches {
+            rom = patch::apply(&rom, patch_data)?;
+        }
+        let kind = self.forced_mapper.or_else(|| MapperKind::detect(&rom));
+        let (header, cart) = match kind {
+            // These headers often lie, so they are not checked against the ROM.
+            Some(kind) => {
+                let header = CartridgeHeader::read(&kind.header(&rom))?;
+                let cart = mbc::unlicensed(kind, &header, rom);
+                (header, cart)
+            }
+            None => {
+                let header = CartridgeHeader::parse(&rom)?;
+                let cart =
+                    mbc::from_header(&header, rom, self.clock.clone(), self.infrared.clone())?;
+                (header, cart)
+            }
+        };
+        self.load_cartridge(header, cart);
+        Ok(())
+    }
+
+    fn load_battery(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        if !self.has_battery() {
+            return Err(CartridgeError::NoBattery);
+        }
+        self.cpu.bus.cart.load_battery(data)?;
+        self.cpu.bus.cart.clear_save_dirty();
+        Ok(())
+    }
+
+    // Inserts a cartridge in place of the one built from a ROM image, e.g. a mapper defined
+    // outside this crate. Call `init` afterwards, as with `load_rom`.
+    pub fn load_cartridge(&mut self, header: CartridgeHeader, cart: Box<dyn Cartridge>) {
+        self.cpu.bus.cart = cart;
+        self.header = Some(header);
+    }
+
+    // The built-in mapper, when the cartridge is one of type `T`.
+    fn cart<T: Cartridge>(&self) -> Option<&T> {
+        let cart: &dyn Any = self.cpu.bus.cart.as_ref();
+        cart.downcast_ref::<T>()
+    }
+
+    fn cart_mut<T: Cartridge>(&mut self) -> Option<&mut T> {
+        let cart: &mut dyn Any = self.cpu.bus.cart.as_mut();
+        cart.downcast_mut::<T>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_cartridge(cartridge_type: u8) -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = cartridge_type;
+        rom[0x149] = 0x02;
+        let header = CartridgeHeader::read(&rom).unwrap();
+        let infrared = Rc::new(RefCell::new(Box::new(Disconnected) as Box<dyn Infrared>));
+        let cart = mbc::from_header(&header, rom, Rc::new(SystemClock), infrared).unwrap();
+        let mut emulator = Emulator::default();
+        emulator.load_cartridge(header, cart);
+        emulator
+    }
+
+    #[test]
+    fn only_imports_saves_for_battery_backed_carts() {
+        let mut emulator = with_cartridge(0x02);
+        assert!(matches!(
+            emulator.load_battery(&[0; 0x2000]),
+            Err(CartridgeError::NoBattery)
+        ));
+        assert_eq!(emulator.export_save(), None);
+
+        let mut emulator = with_cartridge(0x03);
+        assert!(emulator.load_battery(&[1; 0x2000]).is_ok());
+        assert_eq!(emulator.export_save(), Some(vec![1; 0x2000]));
+    }
+}
@@ -0,0 +1,50 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+// The other side of an infrared port, e.g. another emulator or a scripted peer.
+pub trait Infrared {
+    // Called whenever the cartridge switches its IR LED on or off.
+    fn set_led(&mut self, on: bool);
+    // Returns whether the receiver currently sees light.
+    fn light(&self) -> bool;
+}
+
+// Nothing is in front of the port: the LED goes unseen and no light is ever received.
+pub struct Disconnected;
+
+impl Infrared for Disconnected {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn light(&self) -> bool {
+        false
+    }
+}
+
+// One end of a point-to-point link where each side sees the other's LED.
+pub struct InfraredPort {
+    leds: Rc<[Cell<bool>; 2]>,
+    side: usize,
+}
+
+impl InfraredPort {
+    pub fn pair() -> (InfraredPort, InfraredPort) {
+        let leds = Rc::new([Cell::new(false), Cell::new(false)]);
+        (
+            InfraredPort {
+                leds: leds.clone(),
+                side: 0,
+            },
+            InfraredPort { leds, side: 1 },
+        )
+    }
+}
+
+impl Infrared for InfraredPort {
+    fn set_led(&mut self, on: bool) {
+        self.leds[self.side].set(on);
+    }
+
+    fn light(&self) -> bool {
+        self.leds[1 - self.side].get()
+    }
+}
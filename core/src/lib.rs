@@ -1,8 +1,28 @@
+mod archive;
 mod bus;
+pub mod cartridge;
+pub mod clock;
 mod console;
 mod consts;
 mod context;
 mod cpu;
+mod dma;
 mod emulator;
+pub mod infrared;
 mod inst;
+mod mbc;
+mod model;
+mod patch;
+mod png;
+mod ppu;
+mod scale;
+mod screen;
+pub mod state;
 mod timer;
+
+pub use emulator::Emulator;
+pub use mbc::MapperKind;
+pub use model::Model;
+pub use ppu::Renderer;
+pub use scale::Scaler;
+pub use screen::{ColorCorrection, PalettePreset};
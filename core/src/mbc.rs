@@ -0,0 +1,328 @@
+mod camera;
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc7;
+mod mmm01;
+mod multicart;
+mod sachen;
+mod wisdom_tree;
+
+pub use camera::{Camera, IMAGE_HEIGHT as CAMERA_HEIGHT, IMAGE_WIDTH as CAMERA_WIDTH};
+pub use huc3::Huc3;
+pub use mbc3::Mbc3;
+pub use mbc5::Mbc5;
+pub use mbc7::Mbc7;
+
+use crate::cartridge::{self, Cartridge, CartridgeError, CartridgeHeader};
+use crate::clock::Clock;
+use crate::infrared::Infrared;
+use crate::state::{StateReader, StateWriter};
+use huc1::Huc1;
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mmm01::Mmm01;
+use multicart::Multicart;
+use sachen::Sachen;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wisdom_tree::WisdomTree;
+
+// Cartridges without a memory bank controller: up to 32 KiB of ROM and an optional 8 KiB of RAM.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size.min(8 * 1024)],
+            dirty: false,
+        }
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        *self.rom.get(addr as usize).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        let offset = addr as usize - 0xa000;
+        *self.ram.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        let offset = addr as usize - 0xa000;
+        if let Some(cell) = self.ram.get_mut(offset) {
+            self.dirty |= *cell != value;
+            *cell = value;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.ram = ram;
+        Ok(())
+    }
+}
+
+// Mappers that cannot be identified from the cartridge type, either because the header lies
+// or because the cart declares a type that only describes its menu.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapperKind {
+    WisdomTree,
+    SachenMmc1,
+    SachenMmc2,
+    // Bung and EMS flash multicarts.
+    Multicart,
+    Mmm01,
+}
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0104-0133--nintendo-logo
+const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+const LOGO_START: usize = 0x104;
+const TITLE: usize = 0x134;
+const CARTRIDGE_TYPE: usize = 0x147;
+const HEADER_END: usize = 0x150;
+
+impl MapperKind {
+    // Guesses the mapper from the ROM contents. Returns None for carts the header describes.
+    pub fn detect(rom: &[u8]) -> Option<MapperKind> {
+        let header = CartridgeHeader::read(rom).ok()?;
+        let sachen_logo_at = |start: usize| {
+            (0..NINTENDO_LOGO.len()).all(|i| {
+                rom.get(sachen::unscramble((start + i) as u16) as usize) == Some(&NINTENDO_LOGO[i])
+            })
+        };
+
+        if has_mmm01_menu(rom) || is_mmm01(rom, 0) {
+            return Some(MapperKind::Mmm01);
+        }
+        if !logo_at(rom, LOGO_START) {
+            if sachen_logo_at(LOGO_START | 0x80) {
+                return Some(MapperKind::SachenMmc1);
+            }
+            if sachen_logo_at(LOGO_START) {
+                return Some(MapperKind::SachenMmc2);
+            }
+        }
+        let first_bank = &rom[..rom.len().min(0x8000)];
+        let wisdom_tree = [&b"WISDOM TREE"[..], &b"WISDOM\0TREE"[..]]
+            .iter()
+            .any(|name| first_bank.windows(name.len()).any(|w| w == *name));
+        if rom.len() > 0x8000 && matches!(header.cartridge_type(), 0x00 | 0xc0) && wisdom_tree {
+            return Some(MapperKind::WisdomTree);
+        }
+        // Multicart menus declare only their own size, and every game brings its own header.
+        // An overdump mirrors the one header it has, so at least two different ones are needed.
+        if rom.len() > header.rom_size() {
+            let mut headers: Vec<&[u8]> = Vec::new();
+            for start in (0..rom.len() / 0x8000).map(|bank| bank * 0x8000) {
+                if valid_header_at(rom, start) {
+                    let header = &rom[start + TITLE..start + HEADER_END];
+                    if !headers.contains(&header) {
+                        headers.push(header);
+                    }
+                }
+            }
+            if headers.len() >= 2 {
+                return Some(MapperKind::Multicart);
+            }
+        }
+        None
+    }
+
+    // Returns the ROM area to read the cartridge header from.
+    pub fn header(self, rom: &[u8]) -> Cow<'_, [u8]> {
+        match self {
+            MapperKind::SachenMmc1 | MapperKind::SachenMmc2 => {
+                let mut header = rom[..rom.len().min(0x150)].to_vec();
+                for (addr, byte) in header.iter_mut().enumerate().skip(0x100) {
+                    let source = sachen::unscramble(addr as u16) as usize;
+                    *byte = *rom.get(source).unwrap_or(&0xff);
+                }
+                Cow::Owned(header)
+            }
+            // The menu's header, in the last 32 KiB.
+            MapperKind::Mmm01 if has_mmm01_menu(rom) => Cow::Borrowed(&rom[rom.len() - 0x8000..]),
+            _ => Cow::Borrowed(rom),
+        }
+    }
+}
+
+fn logo_at(rom: &[u8], start: usize) -> bool {
+    rom.get(start..start + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..])
+}
+
+// A header the boot ROM would accept: the logo and the header checksum both check out.
+fn valid_header_at(rom: &[u8], start: usize) -> bool {
+    logo_at(rom, start + LOGO_START) && cartridge::header_checksum_valid(&rom[start..])
+}
+
+fn is_mmm01(rom: &[u8], start: usize) -> bool {
+    matches!(rom.get(start + CARTRIDGE_TYPE), Some(0x0b..=0x0d))
+}
+
+// MMM01 carts boot into a menu in the last 32 KiB. Any ROM can have an MMM01 type byte at that
+// offset by chance, so the menu must also have a header the boot ROM would accept.
+fn has_mmm01_menu(rom: &[u8]) -> bool {
+    let start = rom.len().saturating_sub(0x8000);
+    start > 0 && is_mmm01(rom, start) && valid_header_at(rom, start)
+}
+
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0147--cartridge-type
+pub fn from_header(
+    header: &CartridgeHeader,
+    rom: Vec<u8>,
+    clock: Rc<dyn Clock>,
+    infrared: Rc<RefCell<Box<dyn Infrared>>>,
+) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    let ram_size = header.ram_size();
+    let cart: Box<dyn Cartridge> = match header.cartridge_type() {
+        0x00 => Box::new(RomOnly::new(rom, 0)),
+        0x08 | 0x09 => Box::new(RomOnly::new(rom, ram_size)),
+        0x01 => Box::new(Mbc1::new(rom, 0)),
+        0x02 | 0x03 => Box::new(Mbc1::new(rom, ram_size)),
+        0x05 | 0x06 => Box::new(Mbc2::new(rom)),
+        0x0f => Box::new(Mbc3::new(rom, 0, Some(clock))),
+        0x10 => Box::new(Mbc3::new(rom, ram_size, Some(clock))),
+        0x11 => Box::new(Mbc3::new(rom, 0, None)),
+        0x12 | 0x13 => Box::new(Mbc3::new(rom, ram_size, None)),
+        0x19 => Box::new(Mbc5::new(rom, 0, false)),
+        0x1a | 0x1b => Box::new(Mbc5::new(rom, ram_size, false)),
+        0x1c => Box::new(Mbc5::new(rom, 0, true)),
+        0x1d | 0x1e => Box::new(Mbc5::new(rom, ram_size, true)),
+        0x22 => Box::new(Mbc7::new(rom)),
+        0xfc => Box::new(Camera::new(rom)),
+        0xfe => Box::new(Huc3::new(rom, ram_size, clock, infrared)),
+        0xff => Box::new(Huc1::new(rom, ram_size, infrared)),
+        code => return Err(CartridgeError::UnsupportedType(code)),
+    };
+    Ok(cart)
+}
+
+pub fn unlicensed(kind: MapperKind, header: &CartridgeHeader, rom: Vec<u8>) -> Box<dyn Cartridge> {
+    match kind {
+        MapperKind::WisdomTree => Box::new(WisdomTree::new(rom)),
+        MapperKind::SachenMmc1 => Box::new(Sachen::new(rom, false)),
+        MapperKind::SachenMmc2 => Box::new(Sachen::new(rom, true)),
+        MapperKind::Multicart => Box::new(Multicart::new(rom)),
+        MapperKind::Mmm01 => Box::new(Mmm01::new(rom, header.ram_size())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes a header the boot ROM would accept at `start`.
+    fn write_header(rom: &mut [u8], start: usize, title: &str, cartridge_type: u8, rom_size: u8) {
+        let header = &mut rom[start..];
+        header[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        header[TITLE..TITLE + 16].fill(0);
+        header[TITLE..TITLE + title.len()].copy_from_slice(title.as_bytes());
+        header[CARTRIDGE_TYPE] = cartridge_type;
+        header[0x148] = rom_size;
+        header[0x14d] = cartridge::header_checksum(header);
+    }
+
+    #[test]
+    fn mirrored_overdumps_are_not_multicarts() {
+        let mut rom = vec![0; 0x8000];
+        write_header(&mut rom, 0, "GAME", 0x00, 0x00);
+        let overdump = [rom.clone(), rom].concat();
+        assert_eq!(MapperKind::detect(&overdump), None);
+        assert!(matches!(
+            CartridgeHeader::parse(&overdump),
+            Err(CartridgeError::Oversized { .. })
+        ));
+    }
+
+    #[test]
+    fn detects_multicarts_by_their_game_headers() {
+        let mut rom = vec![0; 0x20000];
+        write_header(&mut rom, 0, "MENU", 0x01, 0x00);
+        write_header(&mut rom, 0x8000, "FIRST", 0x00, 0x00);
+        write_header(&mut rom, 0x10000, "SECOND", 0x00, 0x00);
+        assert_eq!(MapperKind::detect(&rom), Some(MapperKind::Multicart));
+    }
+
+    #[test]
+    fn ignores_stray_mmm01_type_bytes() {
+        let mut rom = vec![0; 0x10000];
+        write_header(&mut rom, 0, "GAME", 0x01, 0x01);
+        rom[0x8000 + CARTRIDGE_TYPE] = 0x0b;
+        assert_eq!(MapperKind::detect(&rom), None);
+        assert!(CartridgeHeader::parse(&rom).is_ok());
+    }
+
+    #[test]
+    fn detects_mmm01_menus_in_the_last_bank() {
+        let mut rom = vec![0; 0x10000];
+        write_header(&mut rom, 0, "GAME", 0x01, 0x00);
+        write_header(&mut rom, 0x8000, "MENU", 0x0b, 0x01);
+        assert_eq!(MapperKind::detect(&rom), Some(MapperKind::Mmm01));
+        assert_eq!(MapperKind::Mmm01.header(&rom)[TITLE], b'M');
+
+        // A cart whose own header declares MMM01.
+        let mut rom = vec![0; 0x10000];
+        write_header(&mut rom, 0, "MENU", 0x0d, 0x01);
+        assert_eq!(MapperKind::detect(&rom), Some(MapperKind::Mmm01));
+    }
+
+    #[test]
+    fn leaves_licensed_mbc1_and_mbc5_carts_alone() {
+        for (cartridge_type, rom_size) in [(0x01, 0x02), (0x03, 0x03), (0x19, 0x04), (0x1b, 0x05)] {
+            let len = 0x8000 << rom_size;
+            let mut rom: Vec<u8> = (0..len).map(|i| (i * 7 % 253) as u8).collect();
+            rom[..HEADER_END].fill(0);
+            write_header(&mut rom, 0, "GAME", cartridge_type, rom_size);
+            // Bytes that only look like MMM01 or a second game in later banks.
+            rom[len - 0x8000 + CARTRIDGE_TYPE] = 0x0b;
+            rom.copy_within(0..HEADER_END, 0x8000);
+            assert_eq!(MapperKind::detect(&rom), None, "{:#04x}", cartridge_type);
+            assert!(CartridgeHeader::parse(&rom).is_ok());
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::png;
+use crate::state::{StateReader, StateWriter};
+
+// https://gbdev.io/pandocs/Gameboy_Camera.html
+pub const IMAGE_WIDTH: usize = 128;
+pub const IMAGE_HEIGHT: usize = 112;
+
+// Bit 4 of the RAM bank register maps the sensor registers to 0xa000.
+const REGISTER_SELECT: u8 = 1 << 4;
+const REGISTER_COUNT: usize = 0x36;
+const CAPTURE_BUSY: u8 = 1 << 0;
+
+// The last captured image is written to RAM bank 0, the saved photos follow from bank 1.
+const CAPTURE_OFFSET: usize = 0x0100;
+const PHOTO_OFFSET: usize = 0x2000;
+const PHOTO_SIZE: usize = 0x1000;
+const PHOTO_SLOTS: usize = 30;
+// One byte per slot, 0xff when the slot holds no photo.
+const PHOTO_STATE_OFFSET: usize = 0x11b2;
+
+// Edge enhancement ratios selected by A004 bits 4-6.
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+pub struct Camera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    ram_enable: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    registers: Vec<u8>,
+    capture_cycles: usize,
+    source: Vec<u8>, // grayscale sensor image, 0 = black
+}
+
+impl Camera {
+    pub fn new(rom: Vec<u8>) -> Camera {
+        Camera {
+            rom,
+            ram: vec![0; 128 * 1024],
+            dirty: false,
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: vec![0; REGISTER_COUNT],
+            capture_cycles: 0,
+            source: vec![0x80; IMAGE_WIDTH * IMAGE_HEIGHT],
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        ((self.ram_bank & 0x0f) as usize) * 0x2000 + (addr as usize & 0x1fff)
+    }
+
+    fn exposure(&self) -> u16 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]])
+    }
+
+    // In clock cycles: 32446 M-cycles, plus 512 unless N is set, plus 16 per unit of exposure.
+    fn capture_time(&self) -> usize {
+        let n = self.registers[1] & 0x80 != 0;
+        4 * (32446 + if n { 0 } else { 512 } + 16 * self.exposure() as usize)
+    }
+
+    pub fn set_image(&mut self, pixels: &[u8]) {
+        self.source.copy_from_slice(pixels);
+    }
+
+    // Converts the sensor image to 2-bit colors the way the cartridge's processing chain does:
+    // exposure and gain, optional edge enhancement, inversion, then the dither matrix. The
+    // viewfinder captures every frame, so the capture area does not mark the save as changed;
+    // photos only need saving once the game copies them into an album slot.
+    fn capture(&mut self) {
+        let gain_code = self.registers[1] & 0x1f;
+        let edge_mode = (self.registers[1] >> 5) & 0x03;
+        let exclusive_edge = self.registers[1] & 0x80 != 0;
+        let invert = self.registers[4] & 0x08 != 0;
+        let edge_ratio = EDGE_RATIOS[((self.registers[4] >> 4) & 0x07) as usize];
+
+        // Gain steps by roughly 0.5 dB.
+        let gain = 10f32.powf(gain_code as f32 * 0.5 / 20.0);
+        let scale = self.exposure() as f32 / 0x1000 as f32 * gain;
+        let sensor = |x: usize, y: usize| {
+            let x = x.min(IMAGE_WIDTH - 1);
+            let y = y.min(IMAGE_HEIGHT - 1);
+            self.source[y * IMAGE_WIDTH + x] as f32 * scale
+        };
+
+        for y in 0..IMAGE_HEIGHT {
+            for x in 0..IMAGE_WIDTH {
+                let center = sensor(x, y);
+                let mut value = center;
+                // Edge enhancement is only emulated in the 2D mode the Game Boy Camera uses.
+                if edge_mode == 3 {
+                    let neighbors = sensor(x.saturating_sub(1), y)
+                        + sensor(x + 1, y)
+                        + sensor(x, y.saturating_sub(1))
+                        + sensor(x, y + 1);
+                    let edge = (4.0 * center - neighbors) * edge_ratio;
+                    value = if exclusive_edge { edge } else { center + edge };
+                }
+                if invert {
+                    value = 255.0 - value;
+                }
+                let value = value.clamp(0.0, 255.0) as u8;
+
+                let matrix = 6 + ((y % 4) * 4 + x % 4) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let color = thresholds.iter().filter(|&&t| value < t).count() as u8;
+
+                let tile = (y / 8) * (IMAGE_WIDTH / 8) + x / 8;
+                let offset = CAPTURE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                self.ram[offset] = (self.ram[offset] & !(1 << bit)) | ((color & 1) << bit);
+                self.ram[offset + 1] = (self.ram[offset + 1] & !(1 << bit)) | ((color >> 1) << bit);
+            }
+        }
+    }
+
+    // Decodes a photo saved in the album into a grayscale PNG.
+    pub fn photo_png(&self, slot: usize) -> Option<Vec<u8>> {
+        if slot >= PHOTO_SLOTS || self.ram[PHOTO_STATE_OFFSET + slot] == 0xff {
+            return None;
+        }
+        let tiles = &self.ram[PHOTO_OFFSET + slot * PHOTO_SIZE..];
+        let mut pixels = vec![0; IMAGE_WIDTH * IMAGE_HEIGHT];
+        for y in 0..IMAGE_HEIGHT {
+            for x in 0..IMAGE_WIDTH {
+                let tile = (y / 8) * (IMAGE_WIDTH / 8) + x / 8;
+                let offset = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                let color = ((tiles[offset] >> bit) & 1) | (((tiles[offset + 1] >> bit) & 1) << 1);
+                pixels[y * IMAGE_WIDTH + x] = 0xff - color * 0x55;
+            }
+        }
+        Some(png::encode_grayscale(IMAGE_WIDTH, IMAGE_HEIGHT, &pixels))
+    }
+}
+
+impl Cartridge for Camera {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let offset = (bank % banks) * 0x4000 + (addr as usize & 0x3fff);
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = value & 0x0f == 0x0a,
+            0x2000..=0x3fff => self.rom_bank = value & 0x3f,
+            0x4000..=0x5fff => self.ram_bank = value & 0x1f,
+            _ => {}
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        if self.ram_bank & REGISTER_SELECT != 0 {
+            // Only the status register can be read back.
+            return match addr as usize & 0x7f {
+                0x00 => self.registers[0],
+                _ => 0x00,
+            };
+        }
+        // The RAM cannot be accessed while the sensor is being read out.
+        if self.capture_cycles > 0 {
+            return 0x00;
+        }
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_bank & REGISTER_SELECT != 0 {
+            match addr as usize & 0x7f {
+                0x00 => {
+                    self.registers[0] = value & 0x07;
+                    if value & CAPTURE_BUSY != 0 && self.capture_cycles == 0 {
+                        self.capture_cycles = self.capture_time();
+                    }
+                }
+                reg @ 0x01..=0x35 => self.registers[reg] = value,
+                _ => {}
+            }
+            return;
+        }
+        if self.ram_enable && self.capture_cycles == 0 {
+            let offset = self.ram_offset(addr);
+            self.dirty |= self.ram[offset] != value;
+            self.ram[offset] = value;
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.capture_cycles > 0 {
+            self.capture_cycles -= 1;
+            if self.capture_cycles == 0 {
+                self.capture();
+                self.registers[0] &= !CAPTURE_BUSY;
+            }
+        }
+    }
+
+    // The sensor image is an input and is not part of the state.
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.ram_enable);
+        state.u8(self.rom_bank);
+        state.u8(self.ram_bank);
+        state.u32(self.capture_cycles as u32);
+        state.bytes(&self.registers);
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let ram_enable = state.bool()?;
+        let rom_bank = state.u8()?;
+        let ram_bank = state.u8()?;
+        let capture_cycles = state.u32()? as usize;
+        let mut registers = vec![0; self.registers.len()];
+        state.bytes(&mut registers)?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.ram_enable = ram_enable;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.capture_cycles = capture_cycles;
+        self.registers = registers;
+        self.ram = ram;
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::infrared::Infrared;
+use crate::state::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// https://gbdev.io/pandocs/HuC1.html
+pub struct Huc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    ir_mode: bool, // the IR port replaces RAM at 0xa000-0xbfff
+    rom_bank: u8,
+    ram_bank: u8,
+    infrared: Rc<RefCell<Box<dyn Infrared>>>,
+}
+
+impl Huc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, infrared: Rc<RefCell<Box<dyn Infrared>>>) -> Huc1 {
+        Huc1 {
+            rom,
+            ram: vec![0; ram_size.min(32 * 1024)],
+            dirty: false,
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            infrared,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = (self.ram_bank as usize) * 0x2000 + (addr as usize & 0x1fff);
+        Some(offset % self.ram.len())
+    }
+}
+
+impl Cartridge for Huc1 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let offset = (bank % banks) * 0x4000 + (addr as usize & 0x3fff);
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ir_mode = value & 0x0f == 0x0e,
+            0x2000..=0x3fff => self.rom_bank = value & 0x3f,
+            0x4000..=0x5fff => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        if self.ir_mode {
+            return 0xc0 | self.infrared.borrow().light() as u8;
+        }
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ir_mode {
+            self.infrared.borrow_mut().set_led(value & 0x01 != 0);
+        } else if let Some(offset) = self.ram_offset(addr) {
+            self.dirty |= self.ram[offset] != value;
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.ir_mode);
+        state.u8(self.rom_bank);
+        state.u8(self.ram_bank);
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let ir_mode = state.bool()?;
+        let rom_bank = state.u8()?;
+        let ram_bank = state.u8()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.ir_mode = ir_mode;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.ram = ram;
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::clock::Clock;
+use crate::infrared::Infrared;
+use crate::state::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// https://gbdev.io/pandocs/HuC3.html
+pub struct Huc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    mode: u8, // selects what appears at 0xa000-0xbfff
+    rom_bank: u8,
+    ram_bank: u8,
+    infrared: Rc<RefCell<Box<dyn Infrared>>>,
+    rtc: Huc3Rtc,
+}
+
+impl Huc3 {
+    pub fn new(
+        rom: Vec<u8>,
+        ram_size: usize,
+        clock: Rc<dyn Clock>,
+        infrared: Rc<RefCell<Box<dyn Infrared>>>,
+    ) -> Huc3 {
+        Huc3 {
+            rom,
+            ram: vec![0; ram_size.min(32 * 1024)],
+            dirty: false,
+            mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+            infrared,
+            rtc: Huc3Rtc::new(clock),
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = (self.ram_bank as usize) * 0x2000 + (addr as usize & 0x1fff);
+        Some(offset % self.ram.len())
+    }
+
+    pub fn take_tone(&mut self) -> Option<u8> {
+        self.rtc.tone.take()
+    }
+}
+
+impl Cartridge for Huc3 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let offset = (bank % banks) * 0x4000 + (addr as usize & 0x3fff);
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.mode = value & 0x0f,
+            0x2000..=0x3fff => self.rom_bank = value & 0x7f,
+            0x4000..=0x5fff => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xa => match self.ram_offset(addr) {
+                Some(offset) => self.ram[offset],
+                None => 0xff,
+            },
+            0xc => self.rtc.response(),
+            // Commands complete immediately, so the semaphore always reads as ready.
+            0xd => 0xff,
+            0xe => 0xc0 | self.infrared.borrow().light() as u8,
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        match self.mode {
+            0xa => {
+                if let Some(offset) = self.ram_offset(addr) {
+                    self.dirty |= self.ram[offset] != value;
+                    self.ram[offset] = value;
+                }
+            }
+            0xb => self.rtc.command(value),
+            0xe => self.infrared.borrow_mut().set_led(value & 0x01 != 0),
+            _ => {}
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.mode);
+        state.u8(self.rom_bank);
+        state.u8(self.ram_bank);
+        state.bytes(&self.ram);
+        self.rtc.save(&mut state);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let mode = state.u8()?;
+        let rom_bank = state.u8()?;
+        let ram_bank = state.u8()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        let rtc = Huc3Rtc::load(self.rtc.clock.clone(), &mut state)?;
+        state.finish()?;
+        self.mode = mode;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.ram = ram;
+        self.rtc = rtc;
+        Ok(())
+    }
+}
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+// The time is exchanged through the scratch memory: minute of the day at 0x00-0x02, day
+// counter at 0x03-0x06, least significant nibble first.
+const SCRATCH_TIME: usize = 0x00;
+const SCRATCH_TONE: usize = 0x27;
+
+// Clock and tone generator, driven by writing a command nibble and a 4-bit argument.
+struct Huc3Rtc {
+    clock: Rc<dyn Clock>,
+    minutes: u64,
+    days: u64,
+    last_update: u64,
+    memory: Vec<u8>, // one nibble per address
+    address: u8,
+    last_command: u8,
+    result: u8,
+    tone: Option<u8>,
+}
+
+impl Huc3Rtc {
+    fn new(clock: Rc<dyn Clock>) -> Huc3Rtc {
+        let last_update = clock.now();
+        Huc3Rtc {
+            clock,
+            minutes: 0,
+            days: 0,
+            last_update,
+            memory: vec![0; 256],
+            address: 0,
+            last_command: 0,
+            result: 0,
+            tone: None,
+        }
+    }
+
+    fn save(&mut self, state: &mut StateWriter) {
+        self.update();
+        state.u64(self.minutes);
+        state.u64(self.days);
+        state.u64(self.last_update);
+        state.bytes(&self.memory);
+        state.u8(self.address);
+        state.u8(self.last_command);
+        state.u8(self.result);
+        state.bool(self.tone.is_some());
+        state.u8(self.tone.unwrap_or(0));
+    }
+
+    fn load(clock: Rc<dyn Clock>, state: &mut StateReader) -> Result<Huc3Rtc, CartridgeError> {
+        let mut rtc = Huc3Rtc::new(clock);
+        rtc.minutes = state.u64()?;
+        rtc.days = state.u64()?;
+        rtc.last_update = state.u64()?;
+        state.bytes(&mut rtc.memory)?;
+        rtc.address = state.u8()?;
+        rtc.last_command = state.u8()?;
+        rtc.result = state.u8()?;
+        let has_tone = state.bool()?;
+        let tone = state.u8()?;
+        rtc.tone = has_tone.then_some(tone);
+        // Account for the time that passed since the state was saved.
+        rtc.update();
+        Ok(rtc)
+    }
+
+    // Catch up with the clock source, keeping partial minutes for the next update.
+    fn update(&mut self) {
+        let elapsed = self.clock.now().saturating_sub(self.last_update) / 60;
+        self.last_update += elapsed * 60;
+        let minutes = self.minutes + elapsed;
+        self.minutes = minutes % MINUTES_PER_DAY;
+        self.days = (self.days + minutes / MINUTES_PER_DAY) & 0xffff;
+    }
+
+    fn response(&self) -> u8 {
+        (self.last_command << 4) | self.result
+    }
+
+    fn command(&mut self, value: u8) {
+        let argument = value & 0x0f;
+        self.last_command = (value >> 4) & 0x07;
+        match self.last_command {
+            0x1 => {
+                self.result = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            0x2 => self.memory[self.address as usize] = argument,
+            0x3 => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            0x4 => self.address = (self.address & 0xf0) | argument,
+            0x5 => self.address = (self.address & 0x0f) | (argument << 4),
+            0x6 => self.extended(argument),
+            _ => {}
+        }
+    }
+
+    fn extended(&mut self, argument: u8) {
+        match argument {
+            0x0 => {
+                self.update();
+                let time = self.days << 12 | self.minutes;
+                for i in 0..7 {
+                    self.memory[SCRATCH_TIME + i] = ((time >> (i * 4)) & 0x0f) as u8;
+                }
+            }
+            0x1 => {
+                let time = (0..7).fold(0u64, |time, i| {
+                    time | (self.memory[SCRATCH_TIME + i] as u64) << (i * 4)
+                });
+                self.update();
+                self.minutes = (time & 0xfff) % MINUTES_PER_DAY;
+                self.days = time >> 12;
+            }
+            0x2 => self.result = 0x1,
+            0xe => self.tone = Some(self.memory[SCRATCH_TONE]),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// https://gbdev.io/pandocs/MBC1.html
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    ram_enable: bool,
+    rom_bank: u8,   // 5-bit register (0x2000-0x3fff)
+    upper_bank: u8, // 2-bit register (0x4000-0x5fff)
+    banking_mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+        let multicart = is_multicart(&rom);
+        Mbc1 {
+            rom,
+            // MBC1 can address at most four 8 KiB RAM banks.
+            ram: vec![0; ram_size.min(32 * 1024)],
+            dirty: false,
+            ram_enable: false,
+            rom_bank: 1,
+            upper_bank: 0,
+            banking_mode: false,
+            multicart,
+        }
+    }
+
+    fn upper_bank_shift(&self) -> u32 {
+        // MBC1M carts wire the upper register to bits 4-5 instead of bits 5-6.
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        let banks = (self.rom.len() / 0x4000).max(1);
+        (bank % banks) * 0x4000 + (addr as usize & 0x3fff)
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enable || self.ram.is_empty() {
+            return None;
+        }
+        let bank = if self.banking_mode {
+            self.upper_bank as usize
+        } else {
+            0
+        };
+        Some((bank * 0x2000 + (addr as usize & 0x1fff)) % self.ram.len())
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let upper = (self.upper_bank as usize) << self.upper_bank_shift();
+        let bank = match addr {
+            0x0000..=0x3fff if self.banking_mode => upper,
+            0x0000..=0x3fff => 0,
+            _ => {
+                let lower = if self.multicart {
+                    self.rom_bank & 0x0f
+                } else {
+                    self.rom_bank
+                };
+                upper | lower as usize
+            }
+        };
+        *self.rom.get(self.rom_offset(bank, addr)).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = value & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                // The zero check looks at all 5 bits, even when the ROM is smaller than 512 KiB.
+                self.rom_bank = value & 0x1f;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5fff => self.upper_bank = value & 0x03,
+            0x6000..=0x7fff => self.banking_mode = value & 0x01 != 0,
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(addr) {
+            self.dirty |= self.ram[offset] != value;
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.ram_enable);
+        state.u8(self.rom_bank);
+        state.u8(self.upper_bank);
+        state.bool(self.banking_mode);
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let ram_enable = state.bool()?;
+        let rom_bank = state.u8()?;
+        let upper_bank = state.u8()?;
+        let banking_mode = state.bool()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.ram_enable = ram_enable;
+        self.rom_bank = rom_bank;
+        self.upper_bank = upper_bank;
+        self.banking_mode = banking_mode;
+        self.ram = ram;
+        Ok(())
+    }
+}
+
+// MBC1M multicarts are 1 MiB and repeat the Nintendo logo at the start of each 256 KiB game.
+fn is_multicart(rom: &[u8]) -> bool {
+    const LOGO: std::ops::Range<usize> = 0x0104..0x0134;
+    const GAME_SIZE: usize = 0x40000;
+    rom.len() == 1024 * 1024 && rom[LOGO] == rom[GAME_SIZE + LOGO.start..GAME_SIZE + LOGO.end]
+}
@@ -0,0 +1,102 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// https://gbdev.io/pandocs/MBC2.html
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: Vec<u8>, // built-in 512x4-bit RAM, one nibble per byte
+    dirty: bool,
+    ram_enable: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            rom,
+            ram: vec![0; 512],
+            dirty: false,
+            ram_enable: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let offset = (bank % banks) * 0x4000 + (addr as usize & 0x3fff);
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        // Bit 8 of the address selects between the RAM enable and the ROM bank register.
+        match addr {
+            0x0000..=0x3fff if addr & 0x0100 == 0 => self.ram_enable = value & 0x0f == 0x0a,
+            0x0000..=0x3fff => {
+                self.rom_bank = value & 0x0f;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xff;
+        }
+        // Only the lower 9 address bits are used, so the RAM repeats across 0xa000-0xbfff.
+        0xf0 | self.ram[addr as usize & 0x01ff]
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_enable {
+            let cell = &mut self.ram[addr as usize & 0x01ff];
+            self.dirty |= *cell != value & 0x0f;
+            *cell = value & 0x0f;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.ram_enable);
+        state.u8(self.rom_bank);
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let ram_enable = state.bool()?;
+        let rom_bank = state.u8()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.ram_enable = ram_enable;
+        self.rom_bank = rom_bank;
+        self.ram = ram;
+        Ok(())
+    }
+}
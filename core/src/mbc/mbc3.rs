@@ -0,0 +1,552 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::clock::Clock;
+use crate::state::{StateReader, StateWriter};
+use std::rc::Rc;
+
+// https://gbdev.io/pandocs/MBC3.html
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    ram_enable: bool,
+    rom_bank: u8,
+    ram_bank: u8, // 0x00-0x03 select a RAM bank, 0x08-0x0c select an RTC register
+    latch: u8,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, clock: Option<Rc<dyn Clock>>) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size.min(32 * 1024)],
+            dirty: false,
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            latch: 0xff,
+            rtc: clock.map(Rtc::new),
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = (self.ram_bank as usize) * 0x2000 + (addr as usize & 0x1fff);
+        Some(offset % self.ram.len())
+    }
+
+    pub fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let offset = (bank % banks) * 0x4000 + (addr as usize & 0x3fff);
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = value & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                self.rom_bank = value & 0x7f;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5fff => self.ram_bank = value,
+            0x6000..=0x7fff => {
+                // Writing 0x00 and then 0x01 copies the running clock into the readable registers.
+                if self.latch == 0x00 && value == 0x01 {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.latch();
+                    }
+                }
+                self.latch = value;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xff;
+        }
+        match self.ram_bank {
+            0x00..=0x03 => match self.ram_offset(addr) {
+                Some(offset) => self.ram[offset],
+                None => 0xff,
+            },
+            0x08..=0x0c => match self.rtc.as_ref() {
+                Some(rtc) => rtc.read(self.ram_bank),
+                None => 0xff,
+            },
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enable {
+            return;
+        }
+        match self.ram_bank {
+            0x00..=0x03 => {
+                if let Some(offset) = self.ram_offset(addr) {
+                    self.dirty |= self.ram[offset] != value;
+                    self.ram[offset] = value;
+                }
+            }
+            0x08..=0x0c => {
+                // The clock registers are saved in the RTC footer.
+                if let Some(rtc) = self.rtc.as_mut() {
+                    self.dirty |= rtc.write(self.ram_bank, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Carts with a clock append the RTC footer.
+    fn save_battery(&mut self) -> Vec<u8> {
+        let mut save = self.ram.clone();
+        if let Some(rtc) = self.rtc.as_mut() {
+            save.extend_from_slice(&rtc.save());
+        }
+        save
+    }
+
+    // Accepts saves with or without the RTC footer, since not every emulator writes one.
+    fn load_battery(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let size_error = CartridgeError::SaveSize {
+            expected: self.ram.len(),
+            actual: data.len(),
+        };
+        if data.len() < self.ram.len() {
+            return Err(size_error);
+        }
+        let (ram, footer) = data.split_at(self.ram.len());
+        if !footer.is_empty() {
+            match self.rtc.as_mut() {
+                Some(rtc) => rtc.load(footer)?,
+                None => return Err(size_error),
+            }
+        }
+        self.ram.copy_from_slice(ram);
+        Ok(())
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.ram_enable);
+        state.u8(self.rom_bank);
+        state.u8(self.ram_bank);
+        state.u8(self.latch);
+        state.bytes(&self.ram);
+        if let Some(rtc) = self.rtc.as_mut() {
+            state.bytes(&rtc.save());
+        }
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let ram_enable = state.bool()?;
+        let rom_bank = state.u8()?;
+        let ram_bank = state.u8()?;
+        let latch = state.u8()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        let mut rtc = [0; RTC_STATE_SIZE];
+        if self.rtc.is_some() {
+            state.bytes(&mut rtc)?;
+        }
+        state.finish()?;
+        if let Some(current) = self.rtc.as_mut() {
+            current.load(&rtc)?;
+        }
+        self.ram_enable = ram_enable;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.latch = latch;
+        self.ram = ram;
+        Ok(())
+    }
+}
+
+const DH_DAY_HIGH: u8 = 1 << 0;
+const DH_HALT: u8 = 1 << 6;
+const DH_CARRY: u8 = 1 << 7;
+
+// Size of the RTC footer appended to `.sav` files by VBA-M, BGB and most other emulators.
+pub const RTC_STATE_SIZE: usize = 48;
+
+#[derive(Default, Clone, Copy)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_low: u8,
+    day_high: u8,
+}
+
+impl RtcRegisters {
+    fn get(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0a => self.hours,
+            0x0b => self.day_low,
+            0x0c => self.day_high,
+            _ => unreachable!(),
+        }
+    }
+
+    fn days(&self) -> u64 {
+        ((self.day_high & DH_DAY_HIGH) as u64) << 8 | self.day_low as u64
+    }
+
+    fn set_days(&mut self, days: u64) {
+        self.day_low = days as u8;
+        self.day_high = (self.day_high & !DH_DAY_HIGH) | ((days >> 8) as u8 & DH_DAY_HIGH);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    // Out-of-range values count up to the register width and wrap to 0 without carrying.
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let days = self.days() + days;
+        if days >= 512 {
+            self.day_high |= DH_CARRY;
+        }
+        self.set_days(days % 512);
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        while seconds > 0 && !self.is_valid() {
+            self.tick_second();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+        let total =
+            self.hours as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64 + seconds;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.add_days(total / 86400);
+    }
+}
+
+pub struct Rtc {
+    clock: Rc<dyn Clock>,
+    current: RtcRegisters,
+    latched: RtcRegisters,
+    last_update: u64,
+}
+
+impl Rtc {
+    fn new(clock: Rc<dyn Clock>) -> Rtc {
+        let last_update = clock.now();
+        Rtc {
+            clock,
+            current: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            last_update,
+        }
+    }
+
+    // Catch the running registers up with the clock source.
+    fn update(&mut self) {
+        let now = self.clock.now();
+        if self.current.day_high & DH_HALT == 0 {
+            self.current.advance(now.saturating_sub(self.last_update));
+        }
+        self.last_update = now;
+    }
+
+    fn latch(&mut self) {
+        self.update();
+        self.latched = self.current;
+    }
+
+    fn read(&self, reg: u8) -> u8 {
+        // Unused bits read as 1.
+        let mask = match reg {
+            0x08 | 0x09 => 0x3f,
+            0x0a => 0x1f,
+            0x0b => 0xff,
+            0x0c => DH_DAY_HIGH | DH_HALT | DH_CARRY,
+            _ => unreachable!(),
+        };
+        self.latched.get(reg) | !mask
+    }
+
+    // Returns whether the register changed.
+    fn write(&mut self, reg: u8, value: u8) -> bool {
+        self.update();
+        let old = self.current.get(reg);
+        match reg {
+            0x08 => self.current.seconds = value & 0x3f,
+            0x09 => self.current.minutes = value & 0x3f,
+            0x0a => self.current.hours = value & 0x1f,
+            0x0b => self.current.day_low = value,
+            0x0c => self.current.day_high = value & (DH_DAY_HIGH | DH_HALT | DH_CARRY),
+            _ => unreachable!(),
+        }
+        self.current.get(reg) != old
+    }
+
+    // Layout: current and latched S/M/H/DL/DH as little-endian u32s, then a 64-bit Unix timestamp.
+    pub fn save(&mut self) -> Vec<u8> {
+        self.update();
+        let mut data = Vec::with_capacity(RTC_STATE_SIZE);
+        for regs in [self.current, self.latched] {
+            for reg in 0x08..=0x0c {
+                data.extend_from_slice(&(regs.get(reg) as u32).to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&self.last_update.to_le_bytes());
+        data
+    }
+
+    // Older emulators write a 32-bit timestamp, giving a 44-byte footer; both are accepted.
+    pub fn load(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        if data.len() != RTC_STATE_SIZE && data.len() != RTC_STATE_SIZE - 4 {
+            return Err(CartridgeError::InvalidRtcState(data.len()));
+        }
+        let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap()) as u8;
+        let regs = |base: usize| RtcRegisters {
+            seconds: word(base) & 0x3f,
+            minutes: word(base + 1) & 0x3f,
+            hours: word(base + 2) & 0x1f,
+            day_low: word(base + 3),
+            day_high: word(base + 4) & (DH_DAY_HIGH | DH_HALT | DH_CARRY),
+        };
+        self.current = regs(0);
+        self.latched = regs(5);
+        let mut timestamp = [0; 8];
+        timestamp[..data.len() - 40].copy_from_slice(&data[40..]);
+        self.last_update = u64::from_le_bytes(timestamp);
+        // Account for the time that passed while the emulator was not running.
+        self.update();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const SECONDS: u8 = 0x08;
+    const MINUTES: u8 = 0x09;
+    const HOURS: u8 = 0x0a;
+    const DAY_LOW: u8 = 0x0b;
+    const DAY_HIGH: u8 = 0x0c;
+
+    fn cart(clock: &ManualClock) -> Mbc3 {
+        let mut cart = Mbc3::new(vec![0; 0x8000], 0x2000, Some(Rc::new(clock.clone())));
+        cart.write_rom(0x0000, 0x0a);
+        cart
+    }
+
+    fn write(cart: &mut Mbc3, reg: u8, value: u8) {
+        cart.write_rom(0x4000, reg);
+        cart.write_ram(0xa000, value);
+    }
+
+    fn read(cart: &mut Mbc3, reg: u8) -> u8 {
+        cart.write_rom(0x4000, reg);
+        cart.read_ram(0xa000)
+    }
+
+    fn latch(cart: &mut Mbc3) {
+        cart.write_rom(0x6000, 0x00);
+        cart.write_rom(0x6000, 0x01);
+    }
+
+    #[test]
+    fn reads_the_latched_time() {
+        let clock = ManualClock::new(1_000_000);
+        let mut cart = cart(&clock);
+        clock.advance(5);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 0);
+        latch(&mut cart);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 5);
+        clock.advance(62);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 5);
+        // Writing 0x01 again without 0x00 first does not latch.
+        cart.write_rom(0x6000, 0x01);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 5);
+        latch(&mut cart);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 7);
+        assert_eq!(read(&mut cart, MINUTES) & 0x3f, 1);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let clock = ManualClock::new(0);
+        let mut cart = cart(&clock);
+        write(&mut cart, DAY_HIGH, DH_HALT);
+        clock.advance(100);
+        latch(&mut cart);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 0);
+        write(&mut cart, DAY_HIGH, 0);
+        clock.advance(3);
+        latch(&mut cart);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 3);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let clock = ManualClock::new(0);
+        let mut cart = cart(&clock);
+        write(&mut cart, SECONDS, 59);
+        write(&mut cart, MINUTES, 59);
+        write(&mut cart, HOURS, 23);
+        write(&mut cart, DAY_LOW, 0xff);
+        write(&mut cart, DAY_HIGH, DH_DAY_HIGH);
+        clock.advance(1);
+        latch(&mut cart);
+        assert_eq!(read(&mut cart, SECONDS) & 0x3f, 0);
+        assert_eq!(read(&mut cart, HOURS) & 0x1f, 0);
+        assert_eq!(read(&mut cart, DAY_LOW), 0);
+        let day_high = read(&mut cart, DAY_HIGH);
+        assert_eq!(day_high & DH_DAY_HIGH, 0);
+        assert_ne!(day_high & DH_CARRY, 0);
+        // The carry stays set until cleared.
+        clock.advance(86400);
+        latch(&mut cart);
+        assert_eq!(read(&mut cart, DAY_LOW), 1);
+        assert_ne!(read(&mut cart, DAY_HIGH) & DH_CARRY, 0);
+    }
+
+    #[test]
+    fn out_of_range_values_wrap_without_carrying() {
+        let mut regs = RtcRegisters {
+            seconds: 62,
+            minutes: 5,
+            ..RtcRegisters::default()
+        };
+        regs.advance(1);
+        assert_eq!((regs.seconds, regs.minutes), (63, 5));
+        regs.advance(1);
+        assert_eq!((regs.seconds, regs.minutes), (0, 5));
+        regs.advance(61);
+        assert_eq!((regs.seconds, regs.minutes), (1, 6));
+
+        let mut regs = RtcRegisters {
+            hours: 31,
+            minutes: 59,
+            seconds: 59,
+            ..RtcRegisters::default()
+        };
+        regs.advance(1);
+        assert_eq!(
+            (regs.hours, regs.minutes, regs.seconds, regs.days()),
+            (0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn save_footer_round_trips() {
+        for size in [RTC_STATE_SIZE, RTC_STATE_SIZE - 4] {
+            let clock = ManualClock::new(1_000_000);
+            let mut cart = cart(&clock);
+            write(&mut cart, MINUTES, 12);
+            write(&mut cart, DAY_LOW, 200);
+            clock.advance(30);
+            latch(&mut cart);
+            cart.write_rom(0x4000, 0x00);
+            cart.write_ram(0xa123, 0x42);
+            let mut save = cart.save_battery();
+            assert_eq!(save.len(), 0x2000 + RTC_STATE_SIZE);
+            save.truncate(0x2000 + size);
+
+            // Time passes while the game is not running.
+            clock.advance(15);
+            let mut loaded = self::cart(&clock);
+            loaded.load_battery(&save).unwrap();
+            assert_eq!(loaded.ram[0x123], 0x42);
+            // The latched registers come back as saved.
+            assert_eq!(read(&mut loaded, SECONDS) & 0x3f, 30);
+            assert_eq!(read(&mut loaded, MINUTES) & 0x3f, 12);
+            latch(&mut loaded);
+            assert_eq!(read(&mut loaded, SECONDS) & 0x3f, 45);
+            assert_eq!(read(&mut loaded, DAY_LOW), 200);
+        }
+    }
+
+    #[test]
+    fn only_saved_changes_mark_the_save_dirty() {
+        let clock = ManualClock::new(0);
+        let mut cart = Mbc3::new(vec![0; 0x8000], 0x2000, Some(Rc::new(clock.clone())));
+        cart.write_ram(0xa000, 0x12);
+        assert!(!cart.save_dirty());
+        cart.write_rom(0x0000, 0x0a);
+        cart.write_ram(0xa000, 0x00);
+        assert!(!cart.save_dirty());
+        cart.write_ram(0xa000, 0x12);
+        assert!(cart.save_dirty());
+        cart.clear_save_dirty();
+        latch(&mut cart);
+        write(&mut cart, MINUTES, 0);
+        assert!(!cart.save_dirty());
+        write(&mut cart, MINUTES, 3);
+        assert!(cart.save_dirty());
+    }
+
+    #[test]
+    fn rejects_other_footer_sizes() {
+        let clock = ManualClock::new(0);
+        let mut cart = cart(&clock);
+        let save = vec![0; 0x2000 + 20];
+        assert!(cart.load_battery(&save).is_err());
+    }
+}
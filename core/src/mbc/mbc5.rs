@@ -0,0 +1,139 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// https://gbdev.io/pandocs/MBC5.html
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    ram_enable: bool,
+    rom_bank: u16, // 9-bit, bank 0 can be mapped to 0x4000-0x7fff
+    ram_bank: u8,
+    rumble: bool,
+    motor: bool,
+    motor_started: bool,
+}
+
+// On rumble carts bit 3 of the RAM bank register drives the motor instead of the RAM address.
+const RUMBLE_MOTOR: u8 = 1 << 3;
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size.min(128 * 1024)],
+            dirty: false,
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble,
+            motor: false,
+            motor_started: false,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enable || self.ram.is_empty() {
+            return None;
+        }
+        let offset = (self.ram_bank as usize) * 0x2000 + (addr as usize & 0x1fff);
+        Some(offset % self.ram.len())
+    }
+
+    // Games drive the motor with short pulses, so a pulse that already ended still counts.
+    pub fn take_rumble(&mut self) -> bool {
+        let active = self.motor || self.motor_started;
+        self.motor_started = false;
+        active
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let offset = (bank % banks) * 0x4000 + (addr as usize & 0x3fff);
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = value == 0x0a,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | ((value as u16 & 1) << 8),
+            0x4000..=0x5fff => {
+                if self.rumble {
+                    self.ram_bank = value & 0x07;
+                    self.motor = value & RUMBLE_MOTOR != 0;
+                    self.motor_started |= self.motor;
+                } else {
+                    self.ram_bank = value & 0x0f;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(addr) {
+            self.dirty |= self.ram[offset] != value;
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.ram_enable);
+        state.u16(self.rom_bank);
+        state.u8(self.ram_bank);
+        state.bool(self.motor);
+        state.bool(self.motor_started);
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let ram_enable = state.bool()?;
+        let rom_bank = state.u16()?;
+        let ram_bank = state.u8()?;
+        let motor = state.bool()?;
+        let motor_started = state.bool()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.ram_enable = ram_enable;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.motor = motor;
+        self.motor_started = motor_started;
+        self.ram = ram;
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// https://gbdev.io/pandocs/MBC7.html
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    ram_enable1: bool,
+    ram_enable2: bool,
+    rom_bank: u8,
+    tilt_x: u16,
+    tilt_y: u16,
+    latched_x: u16,
+    latched_y: u16,
+    latch_erased: bool,
+    eeprom: Eeprom,
+}
+
+// Accelerometer reading at rest, and the change in reading for 1g of tilt.
+const ACCEL_CENTER: f32 = 0x81d0 as f32;
+const ACCEL_ONE_G: f32 = 0x70 as f32;
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>) -> Mbc7 {
+        Mbc7 {
+            rom,
+            ram_enable1: false,
+            ram_enable2: false,
+            rom_bank: 1,
+            tilt_x: ACCEL_CENTER as u16,
+            tilt_y: ACCEL_CENTER as u16,
+            latched_x: 0x8000,
+            latched_y: 0x8000,
+            latch_erased: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    // The registers only respond in 0xa000-0xafff, and only when both enables are set.
+    fn register(&self, addr: u16) -> Option<u16> {
+        if self.ram_enable1 && self.ram_enable2 && addr < 0xb000 {
+            Some((addr >> 4) & 0x0f)
+        } else {
+            None
+        }
+    }
+
+    // Tilt is given in units of g along the sensor's X and Y axes.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        let sample = |g: f32| (ACCEL_CENTER + g * ACCEL_ONE_G).clamp(0.0, u16::MAX as f32) as u16;
+        self.tilt_x = sample(x);
+        self.tilt_y = sample(y);
+    }
+}
+
+impl Cartridge for Mbc7 {
+    // The EEPROM contents take the place of save RAM.
+    fn ram(&self) -> &[u8] {
+        &self.eeprom.data
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.eeprom.data
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.eeprom.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.eeprom.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let offset = (bank % banks) * 0x4000 + (addr as usize & 0x3fff);
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable1 = value == 0x0a,
+            0x2000..=0x3fff => self.rom_bank = value & 0x7f,
+            0x4000..=0x5fff => self.ram_enable2 = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match self.register(addr) {
+            Some(0x2) => self.latched_x as u8,
+            Some(0x3) => (self.latched_x >> 8) as u8,
+            Some(0x4) => self.latched_y as u8,
+            Some(0x5) => (self.latched_y >> 8) as u8,
+            Some(0x6) => 0x00,
+            Some(0x8) => self.eeprom.read(),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        match self.register(addr) {
+            Some(0x0) if value == 0x55 => {
+                self.latched_x = 0x8000;
+                self.latched_y = 0x8000;
+                self.latch_erased = true;
+            }
+            // A new sample is only taken after the previous one has been erased.
+            Some(0x1) if value == 0xaa && self.latch_erased => {
+                self.latched_x = self.tilt_x;
+                self.latched_y = self.tilt_y;
+                self.latch_erased = false;
+            }
+            Some(0x8) => self.eeprom.write(value),
+            _ => {}
+        }
+    }
+
+    // The tilt is an input and is not part of the state.
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.ram_enable1);
+        state.bool(self.ram_enable2);
+        state.u8(self.rom_bank);
+        state.u16(self.latched_x);
+        state.u16(self.latched_y);
+        state.bool(self.latch_erased);
+        self.eeprom.save(&mut state);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let ram_enable1 = state.bool()?;
+        let ram_enable2 = state.bool()?;
+        let rom_bank = state.u8()?;
+        let latched_x = state.u16()?;
+        let latched_y = state.u16()?;
+        let latch_erased = state.bool()?;
+        let eeprom = Eeprom::load(&mut state)?;
+        state.finish()?;
+        self.ram_enable1 = ram_enable1;
+        self.ram_enable2 = ram_enable2;
+        self.rom_bank = rom_bank;
+        self.latched_x = latched_x;
+        self.latched_y = latched_y;
+        self.latch_erased = latch_erased;
+        self.eeprom = eeprom;
+        Ok(())
+    }
+}
+
+const EEPROM_CS: u8 = 1 << 7;
+const EEPROM_CLK: u8 = 1 << 6;
+const EEPROM_DI: u8 = 1 << 1;
+const EEPROM_DO: u8 = 1 << 0;
+
+// 128 16-bit words; the save data holds each word in little-endian order.
+const EEPROM_WORDS: usize = 128;
+
+enum EepromState {
+    Idle,              // waiting for the start bit
+    Command(u8),       // number of opcode/address bits received
+    Read(u8),          // number of data bits shifted out of the current word
+    Write(Option<u8>), // receiving data for one word, or for all words when None
+    Done,              // command finished, waiting for CS to fall
+}
+
+// 93LC56 serial EEPROM in 16-bit organisation.
+struct Eeprom {
+    data: Vec<u8>,
+    dirty: bool,
+    pins: u8,
+    data_out: bool,
+    write_enable: bool,
+    state: EepromState,
+    shift: u16,
+    address: u8,
+    bits: u8,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: vec![0xff; EEPROM_WORDS * 2],
+            dirty: false,
+            pins: 0,
+            data_out: true,
+            write_enable: false,
+            state: EepromState::Idle,
+            shift: 0,
+            address: 0,
+            bits: 0,
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        let (tag, value) = match self.state {
+            EepromState::Idle => (0, 0),
+            EepromState::Command(count) => (1, count),
+            EepromState::Read(count) => (2, count),
+            EepromState::Write(Some(address)) => (3, address),
+            EepromState::Write(None) => (4, 0),
+            EepromState::Done => (5, 0),
+        };
+        state.u8(tag);
+        state.u8(value);
+        state.u8(self.pins);
+        state.bool(self.data_out);
+        state.bool(self.write_enable);
+        state.u16(self.shift);
+        state.u8(self.address);
+        state.u8(self.bits);
+        state.bytes(&self.data);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Eeprom, CartridgeError> {
+        let eeprom_state = match (state.u8()?, state.u8()?) {
+            (0, _) => EepromState::Idle,
+            (1, count) => EepromState::Command(count),
+            (2, count) => EepromState::Read(count),
+            (3, address) => EepromState::Write(Some(address)),
+            (4, _) => EepromState::Write(None),
+            (5, _) => EepromState::Done,
+            _ => return Err(CartridgeError::InvalidState),
+        };
+        let mut eeprom = Eeprom::new();
+        eeprom.state = eeprom_state;
+        eeprom.pins = state.u8()?;
+        eeprom.data_out = state.bool()?;
+        eeprom.write_enable = state.bool()?;
+        eeprom.shift = state.u16()?;
+        eeprom.address = state.u8()?;
+        eeprom.bits = state.u8()?;
+        state.bytes(&mut eeprom.data)?;
+        Ok(eeprom)
+    }
+
+    fn read(&self) -> u8 {
+        let data_out = if self.data_out { EEPROM_DO } else { 0 };
+        (self.pins & (EEPROM_CS | EEPROM_CLK | EEPROM_DI)) | data_out
+    }
+
+    fn write(&mut self, value: u8) {
+        let rising_edge = self.pins & EEPROM_CLK == 0 && value & EEPROM_CLK != 0;
+        self.pins = value;
+        if value & EEPROM_CS == 0 {
+            self.state = EepromState::Idle;
+            self.data_out = true;
+            return;
+        }
+        if rising_edge {
+            self.clock(value & EEPROM_DI != 0);
+        }
+    }
+
+    fn word(&self, address: u8) -> u16 {
+        let i = address as usize * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]])
+    }
+
+    fn set_word(&mut self, address: u8, value: u16) {
+        let i = address as usize * 2;
+        self.dirty |= self.data[i..i + 2] != value.to_le_bytes();
+        self.data[i..i + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn clock(&mut self, bit: bool) {
+        match self.state {
+            EepromState::Idle => {
+                if bit {
+                    self.state = EepromState::Command(0);
+                    self.shift = 0;
+                }
+            }
+            EepromState::Command(count) => {
+                self.shift = (self.shift << 1) | bit as u16;
+                if count + 1 == 10 {
+                    self.execute(self.shift);
+                } else {
+                    self.state = EepromState::Command(count + 1);
+                }
+            }
+            EepromState::Read(count) => {
+                // Sequential reads continue into the next word.
+                if count == 16 {
+                    self.address = (self.address + 1) % EEPROM_WORDS as u8;
+                    self.shift = self.word(self.address);
+                }
+                self.data_out = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.state = EepromState::Read(count % 16 + 1);
+            }
+            EepromState::Write(address) => {
+                self.shift = (self.shift << 1) | bit as u16;
+                self.bits += 1;
+                if self.bits == 16 {
+                    if self.write_enable {
+                        match address {
+                            Some(address) => self.set_word(address, self.shift),
+                            None => {
+                                for address in 0..EEPROM_WORDS as u8 {
+                                    self.set_word(address, self.shift);
+                                }
+                            }
+                        }
+                    }
+                    self.finish();
+                }
+            }
+            EepromState::Done => {}
+        }
+    }
+
+    // Commands are a 2-bit opcode followed by 8 address bits, the top one unused.
+    fn execute(&mut self, command: u16) {
+        let opcode = (command >> 8) & 0x03;
+        let address = (command & 0x7f) as u8;
+        self.shift = 0;
+        self.bits = 0;
+        match (opcode, (command >> 6) & 0x03) {
+            (0b10, _) => {
+                // A dummy 0 bit precedes the data.
+                self.address = address;
+                self.shift = self.word(address);
+                self.data_out = false;
+                self.state = EepromState::Read(0);
+            }
+            (0b01, _) => self.state = EepromState::Write(Some(address)),
+            (0b11, _) => {
+                if self.write_enable {
+                    self.set_word(address, 0xffff);
+                }
+                self.finish();
+            }
+            (0b00, 0b11) => {
+                self.write_enable = true;
+                self.finish();
+            }
+            (0b00, 0b00) => {
+                self.write_enable = false;
+                self.finish();
+            }
+            (0b00, 0b10) => {
+                if self.write_enable {
+                    self.dirty |= self.data.iter().any(|&byte| byte != 0xff);
+                    self.data.fill(0xff);
+                }
+                self.finish();
+            }
+            (0b00, 0b01) => self.state = EepromState::Write(None),
+            _ => unreachable!(),
+        }
+    }
+
+    // Writes complete instantly, so DO reports ready straight away.
+    fn finish(&mut self) {
+        self.data_out = true;
+        self.state = EepromState::Done;
+    }
+}
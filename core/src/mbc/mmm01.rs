@@ -0,0 +1,216 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// https://gbdev.io/pandocs/MMM01.html
+// The MMM01 starts unmapped, with the last 32 KiB of ROM holding the menu. The menu sets the
+// outer bank bits and masks for the chosen game, then maps it, which locks them until reset.
+// The multiplex bit, which swaps the RAM bank and upper ROM bank lines, is not emulated.
+pub struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    mapped: bool,
+    ram_enable: bool,
+    rom_bank_low: u8,  // bits 0-4 (0x2000-0x3fff)
+    rom_bank_mid: u8,  // bits 5-6 (0x2000-0x3fff, unmapped only)
+    rom_bank_high: u8, // bits 7-8 (0x4000-0x5fff, unmapped only)
+    rom_bank_mask: u8, // locks bits 1-4 of the low ROM bank once mapped
+    ram_bank_low: u8,  // bits 0-1 (0x4000-0x5fff)
+    ram_bank_high: u8, // bits 2-3 (0x4000-0x5fff, unmapped only)
+    ram_bank_mask: u8, // locks bits 0-1 of the low RAM bank once mapped
+    banking_mode: bool,
+    banking_mode_locked: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mmm01 {
+        Mmm01 {
+            rom,
+            ram: vec![0; ram_size.min(128 * 1024)],
+            dirty: false,
+            mapped: false,
+            ram_enable: false,
+            rom_bank_low: 1,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_bank_mask: 0,
+            banking_mode: false,
+            banking_mode_locked: false,
+        }
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        let banks = (self.rom.len() / 0x4000).max(1);
+        (bank % banks) * 0x4000 + (addr as usize & 0x3fff)
+    }
+
+    // Bits of the low ROM bank that belong to the game's outer bank.
+    fn rom_fixed_bits(&self) -> u8 {
+        if self.mapped {
+            (self.rom_bank_mask & 0x0f) << 1
+        } else {
+            0
+        }
+    }
+
+    fn ram_fixed_bits(&self) -> u8 {
+        if self.mapped {
+            self.ram_bank_mask
+        } else {
+            0
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enable || self.ram.is_empty() {
+            return None;
+        }
+        // As on MBC1, the game's own RAM bank bits only count in banking mode 1.
+        let low = if self.banking_mode {
+            self.ram_bank_low
+        } else {
+            self.ram_bank_low & self.ram_fixed_bits()
+        };
+        let bank = (self.ram_bank_high as usize) << 2 | low as usize;
+        Some((bank * 0x2000 + (addr as usize & 0x1fff)) % self.ram.len())
+    }
+}
+
+impl Cartridge for Mmm01 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let banks = (self.rom.len() / 0x4000).max(2);
+        let outer = (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5;
+        let bank = match addr {
+            0x0000..=0x3fff if !self.mapped => banks - 2,
+            0x0000..=0x3fff => outer | (self.rom_bank_low & self.rom_fixed_bits()) as usize,
+            _ if !self.mapped => banks - 1,
+            _ => {
+                let low = if self.rom_bank_low == 0 {
+                    1
+                } else {
+                    self.rom_bank_low
+                };
+                outer | low as usize
+            }
+        };
+        *self.rom.get(self.rom_offset(bank, addr)).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = value & 0x0f == 0x0a;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3fff => {
+                let fixed = self.rom_fixed_bits();
+                self.rom_bank_low = (self.rom_bank_low & fixed) | (value & 0x1f & !fixed);
+                if !self.mapped {
+                    self.rom_bank_mid = (value >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5fff => {
+                let fixed = self.ram_fixed_bits();
+                self.ram_bank_low = (self.ram_bank_low & fixed) | (value & 0x03 & !fixed);
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (value >> 4) & 0x03;
+                    self.banking_mode_locked = value & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7fff => {
+                if !self.banking_mode_locked {
+                    self.banking_mode = value & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (value >> 2) & 0x0f;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(addr) {
+            self.dirty |= self.ram[offset] != value;
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bool(self.mapped);
+        state.bool(self.ram_enable);
+        state.u8(self.rom_bank_low);
+        state.u8(self.rom_bank_mid);
+        state.u8(self.rom_bank_high);
+        state.u8(self.rom_bank_mask);
+        state.u8(self.ram_bank_low);
+        state.u8(self.ram_bank_high);
+        state.u8(self.ram_bank_mask);
+        state.bool(self.banking_mode);
+        state.bool(self.banking_mode_locked);
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let mapped = state.bool()?;
+        let ram_enable = state.bool()?;
+        let rom_bank_low = state.u8()?;
+        let rom_bank_mid = state.u8()?;
+        let rom_bank_high = state.u8()?;
+        let rom_bank_mask = state.u8()?;
+        let ram_bank_low = state.u8()?;
+        let ram_bank_high = state.u8()?;
+        let ram_bank_mask = state.u8()?;
+        let banking_mode = state.bool()?;
+        let banking_mode_locked = state.bool()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.mapped = mapped;
+        self.ram_enable = ram_enable;
+        self.rom_bank_low = rom_bank_low;
+        self.rom_bank_mid = rom_bank_mid;
+        self.rom_bank_high = rom_bank_high;
+        self.rom_bank_mask = rom_bank_mask;
+        self.ram_bank_low = ram_bank_low;
+        self.ram_bank_high = ram_bank_high;
+        self.ram_bank_mask = ram_bank_mask;
+        self.banking_mode = banking_mode;
+        self.banking_mode_locked = banking_mode_locked;
+        self.ram = ram;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// Flash multicarts such as the Bung Xchanger and EMS 64M carts boot a menu from the start of the
+// ROM, with each game stored at a 32 KiB boundary. They are modelled as an MBC5 behind an outer
+// bank: the menu writes the game's position in 32 KiB units to 0x6000-0x7fff, which locks it,
+// and the game then sees its own ROM from bank 0.
+pub struct Multicart {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool,
+    outer_bank: usize,
+    outer_bank_locked: bool,
+    ram_enable: bool,
+    rom_bank: u16, // 9-bit register (0x2000-0x3fff)
+    ram_bank: u8,  // 4-bit register (0x4000-0x5fff)
+}
+
+// The carts carry enough SRAM for any MBC5 game.
+const RAM_SIZE: usize = 128 * 1024;
+
+impl Multicart {
+    pub fn new(rom: Vec<u8>) -> Multicart {
+        Multicart {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            dirty: false,
+            outer_bank: 0,
+            outer_bank_locked: false,
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enable {
+            return None;
+        }
+        Some(self.ram_bank as usize * 0x2000 + (addr as usize & 0x1fff))
+    }
+}
+
+impl Cartridge for Multicart {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        let offset = self.outer_bank * 0x8000 + bank * 0x4000 + (addr as usize & 0x3fff);
+        *self
+            .rom
+            .get(offset % self.rom.len().max(1))
+            .unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = value == 0x0a,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | (value as u16 & 1) << 8,
+            0x4000..=0x5fff => self.ram_bank = value & 0x0f,
+            0x6000..=0x7fff => {
+                if !self.outer_bank_locked {
+                    self.outer_bank = value as usize;
+                    self.outer_bank_locked = true;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(addr) {
+            self.dirty |= self.ram[offset] != value;
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u32(self.outer_bank as u32);
+        state.bool(self.outer_bank_locked);
+        state.bool(self.ram_enable);
+        state.u16(self.rom_bank);
+        state.u8(self.ram_bank);
+        state.bytes(&self.ram);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let outer_bank = state.u32()? as usize;
+        let outer_bank_locked = state.bool()?;
+        let ram_enable = state.bool()?;
+        let rom_bank = state.u16()?;
+        let ram_bank = state.u8()?;
+        let mut ram = vec![0; self.ram.len()];
+        state.bytes(&mut ram)?;
+        state.finish()?;
+        self.outer_bank = outer_bank;
+        self.outer_bank_locked = outer_bank_locked;
+        self.ram_enable = ram_enable;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.ram = ram;
+        Ok(())
+    }
+}
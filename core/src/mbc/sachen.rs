@@ -0,0 +1,151 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// Sachen MMC1 and MMC2 carts get past the boot ROM's logo check by showing it a Nintendo logo
+// stored elsewhere while the cart is locked, and keep their own logo at 0x0104 for the screen.
+// Header reads also have address lines swapped. The cart unlocks after 0x31 reads from
+// 0x0100-0x01ff, which the boot ROM does by the time it hands over.
+// https://github.com/mgba-emu/mgba/blob/master/src/gb/mbc/unlicensed.c
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Lock {
+    // Header reads are only unscrambled. The MMC2 starts here for the DMG boot ROM and moves
+    // on once the CGB boot ROM touches work RAM, which the cart cannot observe in this emulator.
+    Plain,
+    // Header reads are redirected to 0x0180-0x01ff.
+    Redirected,
+    Unlocked,
+}
+
+const UNLOCK_READS: u8 = 0x31;
+
+pub struct Sachen {
+    rom: Vec<u8>,
+    base_bank: u8,
+    bank_mask: u8,
+    rom_bank: u8,
+    lock: Lock,
+    header_reads: u8,
+}
+
+impl Sachen {
+    pub fn new(rom: Vec<u8>, mmc2: bool) -> Sachen {
+        Sachen {
+            rom,
+            base_bank: 0,
+            bank_mask: 0,
+            rom_bank: 1,
+            lock: if mmc2 { Lock::Plain } else { Lock::Redirected },
+            header_reads: 0,
+        }
+    }
+
+    fn rom_offset(&self, bank: u8, addr: u16) -> usize {
+        let banks = (self.rom.len() / 0x4000).max(1);
+        (bank as usize % banks) * 0x4000 + (addr as usize & 0x3fff)
+    }
+}
+
+impl Cartridge for Sachen {
+    // Without a boot ROM the game starts on an unlocked cart.
+    fn skip_boot(&mut self) {
+        self.lock = Lock::Unlocked;
+    }
+
+    // Counts reads of the header area towards unlocking.
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        if addr & 0xff00 == 0x0100 && self.lock != Lock::Unlocked {
+            self.header_reads += 1;
+            if self.header_reads == UNLOCK_READS {
+                self.lock = Lock::Unlocked;
+            }
+        }
+        self.peek_rom(addr)
+    }
+
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let mut addr = addr;
+        if addr & 0xff00 == 0x0100 {
+            if self.lock == Lock::Redirected {
+                addr |= 0x80;
+            }
+            addr = unscramble(addr);
+        }
+        let bank = match addr {
+            0x0000..=0x3fff => self.base_bank & self.bank_mask,
+            _ => (self.rom_bank & !self.bank_mask) | (self.base_bank & self.bank_mask),
+        };
+        *self.rom.get(self.rom_offset(bank, addr)).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        // The base bank and mask only accept writes while bits 4-5 of the ROM bank are set.
+        let outer_writable = self.rom_bank & 0x30 == 0x30;
+        match addr {
+            0x0000..=0x1fff if outer_writable => self.base_bank = value,
+            0x2000..=0x3fff => self.rom_bank = if value == 0 { 1 } else { value },
+            0x4000..=0x5fff if outer_writable => self.bank_mask = value,
+            _ => {}
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.base_bank);
+        state.u8(self.bank_mask);
+        state.u8(self.rom_bank);
+        state.u8(self.lock as u8);
+        state.u8(self.header_reads);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let base_bank = state.u8()?;
+        let bank_mask = state.u8()?;
+        let rom_bank = state.u8()?;
+        let lock = match state.u8()? {
+            0 => Lock::Plain,
+            1 => Lock::Redirected,
+            2 => Lock::Unlocked,
+            _ => return Err(CartridgeError::InvalidState),
+        };
+        let header_reads = state.u8()?;
+        state.finish()?;
+        self.base_bank = base_bank;
+        self.bank_mask = bank_mask;
+        self.rom_bank = rom_bank;
+        self.lock = lock;
+        self.header_reads = header_reads;
+        Ok(())
+    }
+}
+
+// Swaps address bits 0 and 6, and bits 1 and 4.
+pub fn unscramble(addr: u16) -> u16 {
+    (addr & 0xffac)
+        | (addr & 0x40) >> 6
+        | (addr & 0x10) >> 3
+        | (addr & 0x02) << 3
+        | (addr & 0x01) << 6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reads_count_towards_unlocking() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0184] = 0x01;
+        rom[0x0104] = 0x02;
+        let mut cart = Sachen::new(rom, false);
+        for _ in 0..UNLOCK_READS {
+            assert_eq!(cart.peek_rom(0x0104), 0x01);
+        }
+        for _ in 1..UNLOCK_READS {
+            assert_eq!(cart.read_rom(0x0104), 0x01);
+        }
+        assert_eq!(cart.read_rom(0x0104), 0x02);
+        assert_eq!(cart.peek_rom(0x0104), 0x02);
+    }
+}
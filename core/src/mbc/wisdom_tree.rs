@@ -0,0 +1,43 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::state::{StateReader, StateWriter};
+
+// Wisdom Tree carts switch the whole 0x0000-0x7fff area in 32 KiB banks. The bank number
+// comes from the low bits of the address written to, not from the value.
+pub struct WisdomTree {
+    rom: Vec<u8>,
+    bank: usize,
+}
+
+impl WisdomTree {
+    pub fn new(rom: Vec<u8>) -> WisdomTree {
+        WisdomTree { rom, bank: 0 }
+    }
+}
+
+impl Cartridge for WisdomTree {
+    fn peek_rom(&self, addr: u16) -> u8 {
+        let banks = (self.rom.len() / 0x8000).max(1);
+        let offset = (self.bank % banks) * 0x8000 + addr as usize;
+        *self.rom.get(offset).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, addr: u16, _value: u8) {
+        if let 0x0000..=0x3fff = addr {
+            self.bank = addr as usize & 0x3f;
+        }
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.bank as u8);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mut state = StateReader::new(data);
+        let bank = state.u8()? as usize;
+        state.finish()?;
+        self.bank = bank;
+        Ok(())
+    }
+}
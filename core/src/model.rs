@@ -0,0 +1,65 @@
+use crate::cpu::Registers;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    Dmg0,
+    #[default]
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,
+}
+
+impl Model {
+    // Register state left by the boot ROM, which software reads to detect the model.
+    // https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+    pub fn registers(self, header_checksum: u8, cgb_cartridge: bool) -> Registers {
+        // The DMG and MGB boot ROMs leave H and C set unless the header checksum is 0.
+        let dmg_flags = if header_checksum == 0 { 0x80 } else { 0xb0 };
+        let (a, f, b, c, d, e, h, l) = match self {
+            Model::Dmg0 => (0x01, 0x00, 0xff, 0x13, 0x00, 0xc1, 0x84, 0x03),
+            Model::Dmg => (0x01, dmg_flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d),
+            Model::Mgb => (0xff, dmg_flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60),
+            Model::Sgb2 => (0xff, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60),
+            // In DMG compatibility mode B, H and L depend on the licensee and title;
+            // these are the values for cartridges that get the default palette.
+            Model::Cgb if !cgb_cartridge => (0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7c),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xff, 0x56, 0x00, 0x0d),
+            // The AGB boot ROM ends with an extra INC B, which also clears Z.
+            Model::Agb if !cgb_cartridge => (0x11, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00, 0x7c),
+            Model::Agb => (0x11, 0x00, 0x01, 0x00, 0xff, 0x56, 0x00, 0x0d),
+        };
+        Registers {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xfffe,
+            pc: 0x100,
+        }
+    }
+
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    // The 16-bit divider when the boot ROM hands over: DIV in the upper byte, the phase of
+    // the next increment in the lower. It depends on how long each boot ROM runs.
+    pub fn divider(self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x182c,
+            Model::Dmg | Model::Mgb => 0xabcc,
+            Model::Sgb | Model::Sgb2 => 0xd85c,
+            Model::Cgb | Model::Agb => 0x1ea0,
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use crate::cartridge::MAX_ROM_SIZE;
+use std::fmt;
+
+// Soft-patch formats used for translations and romhacks.
+// IPS: https://zerosoft.zophar.net/ips.php
+// UPS: https://www.romhacking.net/documents/392/
+// BPS: https://www.romhacking.net/documents/746/
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: usize = 0x454f46; // "EOF"
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// UPS and BPS end with the CRC32s of the source, the target and the patch itself.
+const FOOTER_SIZE: usize = 12;
+
+#[derive(Debug)]
+pub enum PatchError {
+    UnknownFormat,
+    Truncated,
+    OutOfBounds,
+    SourceSize { expected: usize, actual: usize },
+    SourceCrc { expected: u32, actual: u32 },
+    TargetCrc { expected: u32, actual: u32 },
+    PatchCrc { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "patch is not in IPS, UPS or BPS format"),
+            PatchError::Truncated => write!(f, "patch data ends unexpectedly"),
+            PatchError::OutOfBounds => write!(f, "patch refers to data outside the ROM"),
+            PatchError::SourceSize { expected, actual } => write!(
+                f,
+                "patch expects a {} byte ROM but got {} bytes",
+                expected, actual
+            ),
+            PatchError::SourceCrc { expected, actual } => write!(
+                f,
+                "patch expects a ROM with CRC32 {:08x} but got {:08x}",
+                expected, actual
+            ),
+            PatchError::TargetCrc { expected, actual } => write!(
+                f,
+                "patched ROM should have CRC32 {:08x} but got {:08x}",
+                expected, actual
+            ),
+            PatchError::PatchCrc { expected, actual } => write!(
+                f,
+                "patch is corrupt: CRC32 should be {:08x} but got {:08x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+pub fn is_patch(data: &[u8]) -> bool {
+    data.starts_with(IPS_MAGIC) || data.starts_with(UPS_MAGIC) || data.starts_with(BPS_MAGIC)
+}
+
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { data, pos }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(PatchError::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, n: usize) -> Result<usize, PatchError> {
+        Ok(self.bytes(n)?.iter().fold(0, |x, &b| (x << 8) | b as usize))
+    }
+
+    // Variable-length integer shared by UPS and BPS.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let b = self.byte()?;
+            value = value
+                .checked_add((b & 0x7f) as usize * shift)
+                .ok_or(PatchError::OutOfBounds)?;
+            if b & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut out = rom.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let size = reader.big_endian(2)?;
+        // A zero size marks a run-length encoded record.
+        let (size, data) = if size == 0 {
+            let size = reader.big_endian(2)?;
+            (size, vec![reader.byte()?; size])
+        } else {
+            (size, reader.bytes(size)?.to_vec())
+        };
+        if out.len() < offset + size {
+            check_target_size(offset + size)?;
+            out.resize(offset + size, 0);
+        }
+        out[offset..offset + size].copy_from_slice(&data);
+    }
+    // Some patches append the size to truncate the output to.
+    if let Ok(size) = reader.big_endian(3) {
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+struct Footer {
+    source_crc: u32,
+    target_crc: u32,
+}
+
+fn read_footer(patch: &[u8]) -> Result<Footer, PatchError> {
+    if patch.len() < FOOTER_SIZE + 4 {
+        return Err(PatchError::Truncated);
+    }
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let word = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+    let patch_crc = crc32fast::hash(&patch[..patch.len() - 4]);
+    if patch_crc != word(2) {
+        return Err(PatchError::PatchCrc {
+            expected: word(2),
+            actual: patch_crc,
+        });
+    }
+    Ok(Footer {
+        source_crc: word(0),
+        target_crc: word(1),
+    })
+}
+
+fn check_source(rom: &[u8], footer: &Footer, size: usize) -> Result<(), PatchError> {
+    if rom.len() != size {
+        return Err(PatchError::SourceSize {
+            expected: size,
+            actual: rom.len(),
+        });
+    }
+    let crc = crc32fast::hash(rom);
+    if crc != footer.source_crc {
+        return Err(PatchError::SourceCrc {
+            expected: footer.source_crc,
+            actual: crc,
+        });
+    }
+    Ok(())
+}
+
+fn check_target(out: &[u8], footer: &Footer) -> Result<(), PatchError> {
+    let crc = crc32fast::hash(out);
+    if crc != footer.target_crc {
+        return Err(PatchError::TargetCrc {
+            expected: footer.target_crc,
+            actual: crc,
+        });
+    }
+    Ok(())
+}
+
+// The target size comes from the patch, so it is bounded before anything is allocated for it.
+fn check_target_size(size: usize) -> Result<(), PatchError> {
+    if size > MAX_ROM_SIZE {
+        return Err(PatchError::OutOfBounds);
+    }
+    Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = read_footer(patch)?;
+    let body = &patch[..patch.len() - FOOTER_SIZE];
+    let mut reader = Reader::new(body, UPS_MAGIC.len());
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_source(rom, &footer, source_size)?;
+    check_target_size(target_size)?;
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+    // Hunks skip ahead, then XOR bytes into the output until a zero byte.
+    let mut pos = 0;
+    while reader.pos < body.len() {
+        pos += reader.number()?;
+        loop {
+            let b = reader.byte()?;
+            if b == 0 {
+                break;
+            }
+            *out.get_mut(pos).ok_or(PatchError::OutOfBounds)? ^= b;
+            pos += 1;
+        }
+        pos += 1;
+    }
+    check_target(&out, &footer)?;
+    Ok(out)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = read_footer(patch)?;
+    let body = &patch[..patch.len() - FOOTER_SIZE];
+    let mut reader = Reader::new(body, BPS_MAGIC.len());
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    check_source(rom, &footer, source_size)?;
+    check_target_size(target_size)?;
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    // Copies use a signed offset relative to the end of the previous copy.
+    let relative = |offset: usize, reader: &mut Reader| -> Result<usize, PatchError> {
+        let data = reader.number()?;
+        let delta = data >> 1;
+        let offset = if data & 1 != 0 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        };
+        offset.ok_or(PatchError::OutOfBounds)
+    };
+    while reader.pos < body.len() {
+        let data = reader.number()?;
+        let length = (data >> 2) + 1;
+        match data & 3 {
+            // SourceRead
+            0 => {
+                let start = out.len();
+                let bytes = rom
+                    .get(start..start + length)
+                    .ok_or(PatchError::OutOfBounds)?;
+                out.extend_from_slice(bytes);
+            }
+            // TargetRead
+            1 => out.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy
+            2 => {
+                source_offset = relative(source_offset, &mut reader)?;
+                let bytes = rom
+                    .get(source_offset..source_offset + length)
+                    .ok_or(PatchError::OutOfBounds)?;
+                out.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // TargetCopy, which may overlap the bytes it is producing
+            _ => {
+                target_offset = relative(target_offset, &mut reader)?;
+                for _ in 0..length {
+                    let b = *out.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    out.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+        if out.len() > target_size {
+            return Err(PatchError::OutOfBounds);
+        }
+    }
+    if out.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+    check_target(&out, &footer)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(mut value: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return out;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    // Appends the UPS/BPS footer: source, target and patch CRC32s.
+    fn finish(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn number_round_trips() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, 123456789] {
+            assert_eq!(Reader::new(&number(value), 0).number().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn ips_applies_records_and_rle() {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);
+        // RLE record running past the end of the ROM.
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+        let out = apply(&rom, &patch).unwrap();
+        assert_eq!(out, [0, 0xaa, 0xbb, 0, 0, 0, 0xcc, 0xcc, 0xcc, 0xcc]);
+    }
+
+    #[test]
+    fn ips_truncates_to_the_trailing_size() {
+        let rom = [1u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x01, 0x02]);
+        patch.extend_from_slice(b"EOF");
+        patch.extend_from_slice(&[0x00, 0x00, 0x03]);
+        assert_eq!(apply(&rom, &patch).unwrap(), [2, 1, 1]);
+    }
+
+    #[test]
+    fn ips_rejects_truncated_records() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x04, 0x01]);
+        assert!(matches!(apply(&[0; 8], &patch), Err(PatchError::Truncated)));
+    }
+
+    #[test]
+    fn ips_rejects_records_past_the_rom_size_limit() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0xff, 0xff, 0x00, 0x00, 0x00, 0xff, 0xff, 0x01]);
+        patch.extend_from_slice(b"EOF");
+        assert!(matches!(
+            apply(&[0; 8], &patch),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        assert!(matches!(
+            apply(&[0; 8], b"NOTAPATCH"),
+            Err(PatchError::UnknownFormat)
+        ));
+    }
+
+    const SOURCE: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+    const UPS_TARGET: &[u8] = &[1, 2, 0x13, 0x14, 5, 6, 7, 8, 9, 10];
+
+    fn ups_body(target_size: usize) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(SOURCE.len()));
+        patch.extend(number(target_size));
+        patch.extend(number(2));
+        patch.extend_from_slice(&[0x10, 0x10, 0x00]);
+        patch.extend(number(3));
+        patch.extend_from_slice(&[9, 10, 0x00]);
+        patch
+    }
+
+    #[test]
+    fn ups_xors_hunks_and_grows_the_rom() {
+        let patch = finish(ups_body(UPS_TARGET.len()), SOURCE, UPS_TARGET);
+        assert_eq!(apply(SOURCE, &patch).unwrap(), UPS_TARGET);
+    }
+
+    #[test]
+    fn ups_checks_the_source_and_target() {
+        let patch = finish(ups_body(UPS_TARGET.len()), SOURCE, UPS_TARGET);
+        let other = [0u8; 8];
+        assert!(matches!(
+            apply(&other, &patch),
+            Err(PatchError::SourceCrc { .. })
+        ));
+        assert!(matches!(
+            apply(&SOURCE[..4], &patch),
+            Err(PatchError::SourceSize {
+                expected: 8,
+                actual: 4
+            })
+        ));
+
+        let patch = finish(ups_body(UPS_TARGET.len()), SOURCE, SOURCE);
+        assert!(matches!(
+            apply(SOURCE, &patch),
+            Err(PatchError::TargetCrc { .. })
+        ));
+    }
+
+    #[test]
+    fn ups_checks_the_patch_crc() {
+        let mut patch = finish(ups_body(UPS_TARGET.len()), SOURCE, UPS_TARGET);
+        patch[8] ^= 0x01;
+        assert!(matches!(
+            apply(SOURCE, &patch),
+            Err(PatchError::PatchCrc { .. })
+        ));
+    }
+
+    #[test]
+    fn ups_rejects_huge_targets_before_allocating() {
+        let patch = finish(ups_body(1 << 40), SOURCE, UPS_TARGET);
+        assert!(matches!(
+            apply(SOURCE, &patch),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+
+    const BPS_SOURCE: &[u8] = b"abcdefgh";
+    const BPS_TARGET: &[u8] = b"abcdghxyabcccc";
+
+    fn bps_body(target_size: usize) -> Vec<u8> {
+        let command = |length: usize, action: usize| number((length - 1) << 2 | action);
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(BPS_SOURCE.len()));
+        patch.extend(number(target_size));
+        patch.extend(number(0));
+        // SourceRead "abcd"
+        patch.extend(command(4, 0));
+        // SourceCopy "gh" from source offset 6
+        patch.extend(command(2, 2));
+        patch.extend(number(6 << 1));
+        // TargetRead "xy"
+        patch.extend(command(2, 1));
+        patch.extend_from_slice(b"xy");
+        // TargetCopy "abc" from target offset 0
+        patch.extend(command(3, 3));
+        patch.extend(number(0));
+        // TargetCopy overlapping its own output: "ccc" from offset 10, 7 past the last copy
+        patch.extend(command(3, 3));
+        patch.extend(number(7 << 1));
+        patch
+    }
+
+    #[test]
+    fn bps_applies_reads_and_copies() {
+        let patch = finish(bps_body(BPS_TARGET.len()), BPS_SOURCE, BPS_TARGET);
+        assert_eq!(apply(BPS_SOURCE, &patch).unwrap(), BPS_TARGET);
+    }
+
+    #[test]
+    fn bps_checks_the_source_and_target() {
+        let patch = finish(bps_body(BPS_TARGET.len()), BPS_SOURCE, BPS_TARGET);
+        assert!(matches!(
+            apply(b"abcdefgX", &patch),
+            Err(PatchError::SourceCrc { .. })
+        ));
+
+        let patch = finish(bps_body(BPS_TARGET.len()), BPS_SOURCE, b"abcdghxyabcccd");
+        assert!(matches!(
+            apply(BPS_SOURCE, &patch),
+            Err(PatchError::TargetCrc { .. })
+        ));
+
+        let mut patch = finish(bps_body(BPS_TARGET.len()), BPS_SOURCE, BPS_TARGET);
+        let last = patch.len() - 1;
+        patch[last] ^= 0x01;
+        assert!(matches!(
+            apply(BPS_SOURCE, &patch),
+            Err(PatchError::PatchCrc { .. })
+        ));
+    }
+
+    #[test]
+    fn bps_rejects_output_past_the_target_size() {
+        let patch = finish(bps_body(BPS_TARGET.len() - 1), BPS_SOURCE, BPS_TARGET);
+        assert!(matches!(
+            apply(BPS_SOURCE, &patch),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn bps_rejects_huge_targets_before_allocating() {
+        let patch = finish(bps_body(usize::MAX >> 8), BPS_SOURCE, BPS_TARGET);
+        assert!(matches!(
+            apply(BPS_SOURCE, &patch),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,624 @@
+mod fifo;
+
+use crate::consts;
+use crate::context::Context;
+use crate::model::Model;
+use fifo::Fifo;
+use wasm_bindgen::prelude::*;
+
+// https://gbdev.io/pandocs/Rendering.html
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: usize = 456;
+const OAM_SCAN_DOTS: usize = 80;
+const DRAWING_DOTS: usize = 172;
+
+// LCDC bits
+const LCD_ENABLE: u8 = 1 << 7;
+const WINDOW_TILE_MAP: u8 = 1 << 6;
+const WINDOW_ENABLE: u8 = 1 << 5;
+const TILE_DATA: u8 = 1 << 4;
+const BG_TILE_MAP: u8 = 1 << 3;
+const OBJ_SIZE: u8 = 1 << 2;
+const OBJ_ENABLE: u8 = 1 << 1;
+const BG_ENABLE: u8 = 1 << 0;
+
+// OAM attribute bits
+// https://gbdev.io/pandocs/OAM.html
+const OBJ_BEHIND_BG: u8 = 1 << 7;
+const OBJ_FLIP_Y: u8 = 1 << 6;
+const OBJ_FLIP_X: u8 = 1 << 5;
+const OBJ_PALETTE: u8 = 1 << 4;
+
+const OBJECTS_PER_LINE: usize = 10;
+
+// STAT bits
+const LYC_INTERRUPT: u8 = 1 << 6;
+const OAM_INTERRUPT: u8 = 1 << 5;
+const VBLANK_INTERRUPT: u8 = 1 << 4;
+const HBLANK_INTERRUPT: u8 = 1 << 3;
+const LYC_EQUAL: u8 = 1 << 2;
+
+// Frame pixels hold the DMG shade 0-3 (lightest first), plus 4 for objects using OBP0 or 8
+// for objects using OBP1, so that the output stage can color each palette on its own.
+const OBP0_PIXEL: u8 = 4;
+const OBP1_PIXEL: u8 = 8;
+
+// The scanline renderer draws each line in one go at the end of a fixed-length mode 3. The
+// FIFO renderer draws a pixel per dot, so mid-line register writes land where they do on
+// hardware, at a higher cost.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Renderer {
+    #[default]
+    Scanline,
+    Fifo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+pub struct Ppu {
+    renderer: Renderer,
+    stat_write_bug: bool,
+    pub vram: [u8; 8 * 1024],
+    pub oam: [u8; 160],
+    pub lcdc: u8,
+    stat: u8, // only the interrupt enable bits; the rest is derived
+    stat_line: bool,
+    pub scy: u8,
+    pub scx: u8,
+    ly: u8,
+    lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+    mode: Mode,
+    dot: usize,               // position within the current line
+    first_line: bool,         // on the first line after the LCD was switched on
+    window_line: u8,          // the window's own line counter, which skips lines it is hidden on
+    window_reached: bool,     // whether LY has matched WY this frame
+    line_objects: Vec<usize>, // OAM indices of the objects on this line, in priority order
+    fifo: Fifo,
+    // Lines are drawn into `buffer`, which is copied to `frame` at the start of VBlank so
+    // that `frame` always holds a complete picture.
+    buffer: Vec<u8>,
+    pub frame: Vec<u8>,
+    frame_ready: bool,
+}
+
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu::new(Model::default(), Renderer::default())
+    }
+}
+
+impl Ppu {
+    pub fn new(model: Model, renderer: Renderer) -> Ppu {
+        Ppu {
+            renderer,
+            stat_write_bug: !model.is_cgb(),
+            vram: [0; 8 * 1024],
+            oam: [0; 160],
+            lcdc: 0,
+            stat: 0,
+            stat_line: false,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            dot: 0,
+            first_line: false,
+            window_line: 0,
+            window_reached: false,
+            line_objects: Vec::with_capacity(OBJECTS_PER_LINE),
+            fifo: Fifo::new(),
+            buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+        }
+    }
+
+    // The state the boot ROM leaves behind: LCD on, showing the background with BGP 0xfc.
+    pub fn after_boot(model: Model, renderer: Renderer) -> Ppu {
+        Ppu {
+            lcdc: 0x91,
+            bgp: 0xfc,
+            mode: Mode::OamScan,
+            ..Ppu::new(model, renderer)
+        }
+    }
+
+    // Advances by one dot (one clock cycle).
+    pub fn tick(&mut self, ctx: &mut Context) {
+        if self.lcdc & LCD_ENABLE == 0 {
+            return;
+        }
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            // LY already reads 0 for most of line 153, see below.
+            if self.mode == Mode::VBlank && self.ly == 0 {
+                self.start_line(ctx);
+                return;
+            }
+            self.ly += 1;
+            self.update_stat_line(ctx);
+            match self.ly {
+                1..=143 => self.start_line(ctx),
+                144 => {
+                    self.frame.copy_from_slice(&self.buffer);
+                    self.frame_ready = true;
+                    self.set_mode(ctx, Mode::VBlank);
+                    ctx.interrupt_flag |= consts::VBLANK_INTERRUPT;
+                }
+                _ => {}
+            }
+        } else if self.ly == 153 && self.dot == 4 {
+            // LY wraps to 0 one M-cycle into line 153, so LYC=0 matches there.
+            self.ly = 0;
+            self.update_stat_line(ctx);
+        } else if (self.mode == Mode::OamScan || self.first_line) && self.dot == OAM_SCAN_DOTS {
+            self.first_line = false;
+            self.scan_oam();
+            if self.renderer == Renderer::Fifo {
+                self.start_fifo();
+            }
+            self.set_mode(ctx, Mode::Drawing);
+        } else if self.mode == Mode::Drawing {
+            let done = match self.renderer {
+                Renderer::Scanline => self.dot == OAM_SCAN_DOTS + DRAWING_DOTS,
+                Renderer::Fifo => self.fifo_dot(),
+            };
+            if done {
+                if self.renderer == Renderer::Scanline {
+                    self.render_line();
+                }
+                self.set_mode(ctx, Mode::HBlank);
+            }
+        }
+    }
+
+    fn start_line(&mut self, ctx: &mut Context) {
+        if self.ly == 0 {
+            self.window_line = 0;
+            self.window_reached = false;
+        }
+        if self.ly == self.wy {
+            self.window_reached = true;
+        }
+        self.set_mode(ctx, Mode::OamScan);
+    }
+
+    fn object_height(&self) -> u8 {
+        if self.lcdc & OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // Picks the first 10 objects in OAM order that overlap this line, whatever their X. On DMG,
+    // the one with the smaller X is drawn on top, and OAM order breaks ties.
+    fn scan_oam(&mut self) {
+        let height = self.object_height();
+        let ly = self.ly.wrapping_add(16);
+        self.line_objects.clear();
+        for index in 0..40 {
+            let y = self.oam[index * 4];
+            if ly >= y && ly < y.wrapping_add(height) {
+                self.line_objects.push(index);
+                if self.line_objects.len() == OBJECTS_PER_LINE {
+                    break;
+                }
+            }
+        }
+        let oam = &self.oam;
+        self.line_objects.sort_by_key(|&index| oam[index * 4 + 1]);
+    }
+
+    // The topmost opaque object pixel at screen position x, if it is not hidden behind the
+    // background.
+    fn object_pixel(&self, x: usize, bg_color: u8) -> Option<u8> {
+        let height = self.object_height();
+        for &index in &self.line_objects {
+            let object = &self.oam[index * 4..index * 4 + 4];
+            let (y, object_x, tile, attributes) = (object[0], object[1], object[2], object[3]);
+            // X is offset by 8, so objects can be partly or fully off the left edge.
+            let column = x + 8;
+            if column < object_x as usize || column >= object_x as usize + 8 {
+                continue;
+            }
+            let mut row = self.ly.wrapping_add(16).wrapping_sub(y);
+            if attributes & OBJ_FLIP_Y != 0 {
+                row = height - 1 - row;
+            }
+            let mut bit = (7 - (column - object_x as usize)) as u8;
+            if attributes & OBJ_FLIP_X != 0 {
+                bit = 7 - bit;
+            }
+            // In 8x16 mode, the top tile is the even one.
+            let tile = if height == 16 { tile & 0xfe } else { tile };
+            let addr = tile as usize * 16 + row as usize * 2;
+            let color = ((self.vram[addr + 1] >> bit) & 1) << 1 | ((self.vram[addr] >> bit) & 1);
+            // Color 0 is transparent, letting lower-priority objects show through.
+            if color == 0 {
+                continue;
+            }
+            if attributes & OBJ_BEHIND_BG != 0 && bg_color != 0 {
+                return None;
+            }
+            let pixel = if attributes & OBJ_PALETTE != 0 {
+                OBP1_PIXEL | ((self.obp1 >> (color * 2)) & 0x03)
+            } else {
+                OBP0_PIXEL | ((self.obp0 >> (color * 2)) & 0x03)
+            };
+            return Some(pixel);
+        }
+        None
+    }
+
+    // https://gbdev.io/pandocs/Tile_Maps.html
+    fn render_line(&mut self) {
+        let ly = self.ly as usize;
+        let window = self.lcdc & WINDOW_ENABLE != 0 && self.window_reached && self.wx <= 166;
+        let mut window_drawn = false;
+        for x in 0..SCREEN_WIDTH {
+            // On DMG, clearing LCDC bit 0 blanks both the background and the window.
+            let color = if self.lcdc & BG_ENABLE == 0 {
+                0
+            } else if window && x + 7 >= self.wx as usize {
+                window_drawn = true;
+                let window_x = (x + 7 - self.wx as usize) as u8;
+                self.tile_pixel(WINDOW_TILE_MAP, window_x, self.window_line)
+            } else {
+                let bg_x = self.scx.wrapping_add(x as u8);
+                let bg_y = self.scy.wrapping_add(ly as u8);
+                self.tile_pixel(BG_TILE_MAP, bg_x, bg_y)
+            };
+            let object = if self.lcdc & OBJ_ENABLE != 0 {
+                self.object_pixel(x, color)
+            } else {
+                None
+            };
+            let pixel = object.unwrap_or((self.bgp >> (color * 2)) & 0x03);
+            self.buffer[ly * SCREEN_WIDTH + x] = pixel;
+        }
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    // 0x8000 addressing uses unsigned tile numbers; 0x8800 addressing uses signed ones
+    // relative to 0x9000.
+    fn tile_data_addr(&self, tile: u8) -> usize {
+        if self.lcdc & TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + tile as i8 as isize * 16) as usize
+        }
+    }
+
+    // Color index 0-3 of the pixel at (x, y) in the 256x256 area covered by a tile map.
+    // https://gbdev.io/pandocs/Tile_Data.html
+    fn tile_pixel(&self, map_select: u8, x: u8, y: u8) -> u8 {
+        let map = if self.lcdc & map_select != 0 {
+            0x1c00
+        } else {
+            0x1800
+        };
+        let tile = self.vram[map + (y as usize / 8) * 32 + x as usize / 8];
+        let row = self.tile_data_addr(tile) + (y as usize % 8) * 2;
+        let bit = 7 - x % 8;
+        let low = (self.vram[row] >> bit) & 1;
+        let high = (self.vram[row + 1] >> bit) & 1;
+        high << 1 | low
+    }
+
+    fn set_mode(&mut self, ctx: &mut Context, mode: Mode) {
+        self.mode = mode;
+        self.update_stat_line(ctx);
+    }
+
+    // https://gbdev.io/pandocs/Interrupt_Sources.html#int-48--stat-interrupt
+    // The enabled sources are ORed into one line, and only its rising edge requests an
+    // interrupt. A source that becomes active while another holds the line high is lost.
+    fn update_stat_line(&mut self, ctx: &mut Context) {
+        let line = self.stat_sources();
+        if line && !self.stat_line {
+            ctx.interrupt_flag |= consts::LCD_STAT_INTERRUPT;
+        }
+        self.stat_line = line;
+    }
+
+    fn stat_sources(&self) -> bool {
+        let mode = match self.mode {
+            Mode::HBlank => HBLANK_INTERRUPT,
+            Mode::VBlank => VBLANK_INTERRUPT,
+            Mode::OamScan => OAM_INTERRUPT,
+            Mode::Drawing => 0,
+        };
+        self.stat & mode != 0 || (self.stat & LYC_INTERRUPT != 0 && self.ly == self.lyc)
+    }
+
+    // Whether a frame has been completed since the last call.
+    pub fn take_frame(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    // https://gbdev.io/pandocs/Rendering.html#ppu-modes
+    // The PPU holds VRAM while drawing and OAM while scanning or drawing; the CPU reads 0xff
+    // and its writes are dropped. Both are free while the LCD is off.
+    pub fn vram_accessible(&self) -> bool {
+        self.mode != Mode::Drawing
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        self.mode != Mode::OamScan && self.mode != Mode::Drawing
+    }
+
+    // https://gbdev.io/pandocs/LCDC.html#lcdc7--lcd-enable
+    pub fn write_lcdc(&mut self, ctx: &mut Context, value: u8) {
+        let was_enabled = self.lcdc & LCD_ENABLE != 0;
+        self.lcdc = value;
+        match (was_enabled, value & LCD_ENABLE != 0) {
+            // LY stays at 0 and the PPU idles in HBlank while the LCD is off.
+            // The screen goes blank.
+            (true, false) => {
+                self.ly = 0;
+                self.dot = 0;
+                self.mode = Mode::HBlank;
+                self.stat_line = false;
+                self.buffer.fill(0);
+                self.frame.fill(0);
+                self.frame_ready = true;
+            }
+            // The first line after switching the LCD on has no OAM scan: it stays in mode 0,
+            // with OAM accessible, until drawing starts.
+            (false, true) => {
+                self.window_line = 0;
+                self.window_reached = self.wy == 0;
+                self.first_line = true;
+                self.update_stat_line(ctx);
+            }
+            _ => {}
+        }
+    }
+
+    // https://gbdev.io/pandocs/STAT.html
+    pub fn stat(&self) -> u8 {
+        let lyc_equal = if self.ly == self.lyc { LYC_EQUAL } else { 0 };
+        0x80 | self.stat | lyc_equal | self.mode as u8
+    }
+
+    pub fn write_stat(&mut self, ctx: &mut Context, value: u8) {
+        let enabled = self.lcdc & LCD_ENABLE != 0;
+        // On DMG, the write enables every source for one cycle, so writing STAT during HBlank,
+        // VBlank or LY=LYC requests an interrupt whatever is written.
+        if enabled && self.stat_write_bug {
+            self.stat = HBLANK_INTERRUPT | VBLANK_INTERRUPT | LYC_INTERRUPT;
+            self.update_stat_line(ctx);
+        }
+        self.stat = value & 0x78;
+        if enabled {
+            self.update_stat_line(ctx);
+        }
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn lyc(&self) -> u8 {
+        self.lyc
+    }
+
+    pub fn write_lyc(&mut self, ctx: &mut Context, value: u8) {
+        self.lyc = value;
+        if self.lcdc & LCD_ENABLE != 0 {
+            self.update_stat_line(ctx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(ppu: &mut Ppu, ctx: &mut Context, dots: usize) {
+        for _ in 0..dots {
+            ppu.tick(ctx);
+        }
+    }
+
+    fn mode(ppu: &Ppu) -> u8 {
+        ppu.stat() & 0x03
+    }
+
+    #[test]
+    fn modes_change_at_fixed_dots() {
+        let mut ppu = Ppu::after_boot(Model::Dmg, Renderer::Scanline);
+        let mut ctx = Context::default();
+        for line in 0..144 {
+            assert_eq!((ppu.ly(), mode(&ppu)), (line, 2));
+            run(&mut ppu, &mut ctx, 79);
+            assert_eq!(mode(&ppu), 2);
+            run(&mut ppu, &mut ctx, 1);
+            assert_eq!(mode(&ppu), 3);
+            run(&mut ppu, &mut ctx, DRAWING_DOTS - 1);
+            assert_eq!(mode(&ppu), 3);
+            run(&mut ppu, &mut ctx, 1);
+            assert_eq!(mode(&ppu), 0);
+            run(
+                &mut ppu,
+                &mut ctx,
+                DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS,
+            );
+        }
+        assert_eq!((ppu.ly(), mode(&ppu)), (144, 1));
+        assert_ne!(ctx.interrupt_flag & consts::VBLANK_INTERRUPT, 0);
+        run(&mut ppu, &mut ctx, 9 * DOTS_PER_LINE);
+        assert_eq!((ppu.ly(), mode(&ppu)), (153, 1));
+    }
+
+    #[test]
+    fn ly_wraps_early_on_line_153() {
+        let mut ppu = Ppu::after_boot(Model::Dmg, Renderer::Scanline);
+        let mut ctx = Context::default();
+        ppu.write_stat(&mut ctx, LYC_INTERRUPT);
+        run(&mut ppu, &mut ctx, 153 * DOTS_PER_LINE);
+        ctx.interrupt_flag = 0;
+        run(&mut ppu, &mut ctx, 3);
+        assert_eq!(ppu.ly(), 153);
+        assert_eq!(ppu.stat() & LYC_EQUAL, 0);
+        run(&mut ppu, &mut ctx, 1);
+        assert_eq!((ppu.ly(), mode(&ppu)), (0, 1));
+        assert_ne!(ppu.stat() & LYC_EQUAL, 0);
+        assert_ne!(ctx.interrupt_flag & consts::LCD_STAT_INTERRUPT, 0);
+        // The frame then starts over without LY changing again.
+        ctx.interrupt_flag = 0;
+        run(&mut ppu, &mut ctx, DOTS_PER_LINE - 4);
+        assert_eq!((ppu.ly(), mode(&ppu)), (0, 2));
+        assert_eq!(ctx.interrupt_flag & consts::LCD_STAT_INTERRUPT, 0);
+    }
+
+    fn access(ppu: &Ppu) -> (bool, bool) {
+        (ppu.vram_accessible(), ppu.oam_accessible())
+    }
+
+    #[test]
+    fn vram_and_oam_are_locked_by_mode() {
+        let mut ppu = Ppu::after_boot(Model::Dmg, Renderer::Scanline);
+        let mut ctx = Context::default();
+        assert_eq!(access(&ppu), (true, false));
+        run(&mut ppu, &mut ctx, OAM_SCAN_DOTS);
+        assert_eq!(access(&ppu), (false, false));
+        run(&mut ppu, &mut ctx, DRAWING_DOTS);
+        assert_eq!(access(&ppu), (true, true));
+        run(
+            &mut ppu,
+            &mut ctx,
+            144 * DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS,
+        );
+        assert_eq!(mode(&ppu), 1);
+        assert_eq!(access(&ppu), (true, true));
+    }
+
+    #[test]
+    fn vram_and_oam_are_free_while_the_lcd_is_off() {
+        let mut ppu = Ppu::after_boot(Model::Dmg, Renderer::Scanline);
+        let mut ctx = Context::default();
+        run(&mut ppu, &mut ctx, OAM_SCAN_DOTS);
+        ppu.write_lcdc(&mut ctx, 0x11);
+        for _ in 0..2 {
+            assert_eq!(access(&ppu), (true, true));
+            run(&mut ppu, &mut ctx, DOTS_PER_LINE);
+        }
+        // The first line after switching back on skips the OAM scan.
+        ppu.write_lcdc(&mut ctx, 0x91);
+        assert_eq!((ppu.ly(), mode(&ppu)), (0, 0));
+        run(&mut ppu, &mut ctx, OAM_SCAN_DOTS - 1);
+        assert_eq!(access(&ppu), (true, true));
+        run(&mut ppu, &mut ctx, 1);
+        assert_eq!(access(&ppu), (false, false));
+        run(&mut ppu, &mut ctx, DOTS_PER_LINE - OAM_SCAN_DOTS);
+        assert_eq!((ppu.ly(), mode(&ppu)), (1, 2));
+        assert_eq!(access(&ppu), (true, false));
+    }
+
+    #[test]
+    fn stat_interrupts_fire_on_the_rising_edge() {
+        let mut ppu = Ppu::after_boot(Model::Cgb, Renderer::Scanline);
+        let mut ctx = Context::default();
+        ppu.write_stat(&mut ctx, LYC_INTERRUPT | HBLANK_INTERRUPT);
+        assert_ne!(ctx.interrupt_flag & consts::LCD_STAT_INTERRUPT, 0);
+        // LY=LYC holds the line high through HBlank, so that does not fire again.
+        ctx.interrupt_flag = 0;
+        run(&mut ppu, &mut ctx, OAM_SCAN_DOTS + DRAWING_DOTS);
+        assert_eq!(mode(&ppu), 0);
+        assert_eq!(ctx.interrupt_flag & consts::LCD_STAT_INTERRUPT, 0);
+        // The line drops with the OAM scan of line 1 and rises again at its HBlank.
+        run(&mut ppu, &mut ctx, DOTS_PER_LINE);
+        assert_eq!((ppu.ly(), mode(&ppu)), (1, 0));
+        assert_ne!(ctx.interrupt_flag & consts::LCD_STAT_INTERRUPT, 0);
+    }
+
+    #[test]
+    fn writing_stat_on_dmg_fires_spuriously() {
+        for (model, dots, fires) in [
+            (Model::Dmg, OAM_SCAN_DOTS + DRAWING_DOTS, true),
+            (Model::Cgb, OAM_SCAN_DOTS + DRAWING_DOTS, false),
+            // Mode 3 with LY != LYC has no source to enable.
+            (Model::Dmg, OAM_SCAN_DOTS, false),
+        ] {
+            let mut ppu = Ppu::after_boot(model, Renderer::Scanline);
+            let mut ctx = Context::default();
+            ppu.write_lyc(&mut ctx, 5);
+            run(&mut ppu, &mut ctx, dots);
+            ppu.write_stat(&mut ctx, 0);
+            let fired = ctx.interrupt_flag & consts::LCD_STAT_INTERRUPT != 0;
+            assert_eq!(fired, fires, "{:?} in mode {}", model, mode(&ppu));
+        }
+    }
+
+    // Objects on line 0 drawn from tile 1, solid color 3, or tile 2, solid color 1.
+    fn with_objects(renderer: Renderer, objects: &[(u8, u8)]) -> Ppu {
+        let mut ppu = Ppu::after_boot(Model::Dmg, renderer);
+        ppu.lcdc |= OBJ_ENABLE;
+        ppu.obp0 = 0xe4;
+        ppu.vram[16..32].fill(0xff);
+        ppu.vram[32..48].copy_from_slice(&[0xff, 0x00].repeat(8));
+        for (index, &(x, tile)) in objects.iter().enumerate() {
+            ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[16, x, tile, 0]);
+        }
+        ppu
+    }
+
+    fn first_line(ppu: &mut Ppu) -> Vec<u8> {
+        let mut ctx = Context::default();
+        run(ppu, &mut ctx, DOTS_PER_LINE);
+        ppu.buffer[..SCREEN_WIDTH].to_vec()
+    }
+
+    #[test]
+    fn only_the_first_ten_objects_on_a_line_are_drawn() {
+        // Objects off the left edge still count towards the limit.
+        let mut objects = vec![(0, 1); 10];
+        objects.push((58, 1));
+        let mut ppu = with_objects(Renderer::Scanline, &objects);
+        let line = first_line(&mut ppu);
+        assert_eq!(ppu.line_objects.len(), OBJECTS_PER_LINE);
+        assert!(line.iter().all(|&pixel| pixel == 0));
+
+        objects.remove(0);
+        let mut ppu = with_objects(Renderer::Scanline, &objects);
+        assert_eq!(first_line(&mut ppu)[50], OBP0_PIXEL | 3);
+    }
+
+    #[test]
+    fn smaller_x_wins_then_oam_order() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            // The later object in OAM is further left, so it is drawn on top where they overlap.
+            let mut ppu = with_objects(renderer, &[(28, 2), (24, 1), (40, 2), (40, 1)]);
+            let line = first_line(&mut ppu);
+            assert_eq!(line[16..24], [OBP0_PIXEL | 3; 8]);
+            assert_eq!(line[24..28], [OBP0_PIXEL | 1; 4]);
+            // At the same X, the earlier object wins.
+            assert_eq!(line[32..40], [OBP0_PIXEL | 1; 8]);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use super::{
+    Ppu, BG_ENABLE, BG_TILE_MAP, OBJ_BEHIND_BG, OBJ_ENABLE, OBJ_FLIP_X, OBJ_FLIP_Y, OBJ_PALETTE,
+    OBP0_PIXEL, OBP1_PIXEL, SCREEN_WIDTH, WINDOW_ENABLE, WINDOW_TILE_MAP,
+};
+use std::collections::VecDeque;
+
+// https://gbdev.io/pandocs/pixel_fifo.html
+// Mode 3 as the hardware does it: a fetcher reads one tile row every 6 dots into the background
+// FIFO, which shifts out one pixel per dot. Fine scrolling, the window and objects each stall
+// the FIFO, so mode 3 lasts 172 to 289 dots depending on what is on the line.
+
+// The fetcher's first tile is thrown away, which delays the first pixel.
+const STARTUP_DOTS: u8 = 6;
+const OBJECT_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjectPixel {
+    color: u8,
+    obp1: bool,
+    behind_bg: bool,
+}
+
+pub struct Fifo {
+    bg: VecDeque<u8>,
+    objects: VecDeque<ObjectPixel>,
+    step: Step,
+    step_dots: u8,
+    startup: u8,
+    tile_x: u8, // tile column being fetched, counted from the left of the screen or window
+    tile: u8,
+    data_low: u8,
+    data_high: u8,
+    window: bool,
+    window_drawn: bool,
+    discard: u8,                       // pixels still to drop for SCX fine scrolling
+    x: usize,                          // next screen column to draw
+    object_fetch: Option<(usize, u8)>, // OAM index and dots left
+    tile_stalled: bool, // whether an object has already waited on the tile being shifted out
+    fetched_objects: usize, // how many of the line's objects have been fetched
+}
+
+impl Fifo {
+    pub fn new() -> Fifo {
+        Fifo {
+            bg: VecDeque::with_capacity(16),
+            objects: VecDeque::with_capacity(8),
+            step: Step::Tile,
+            step_dots: 0,
+            startup: STARTUP_DOTS,
+            tile_x: 0,
+            tile: 0,
+            data_low: 0,
+            data_high: 0,
+            window: false,
+            window_drawn: false,
+            discard: 0,
+            x: 0,
+            object_fetch: None,
+            tile_stalled: false,
+            fetched_objects: 0,
+        }
+    }
+
+    fn restart_fetcher(&mut self) {
+        self.step = Step::Tile;
+        self.step_dots = 0;
+        self.tile_x = 0;
+    }
+}
+
+impl Ppu {
+    pub(super) fn start_fifo(&mut self) {
+        self.fifo = Fifo::new();
+        self.fifo.discard = self.scx % 8;
+    }
+
+    // Runs one dot of mode 3. Returns true once the line is complete.
+    pub(super) fn fifo_dot(&mut self) -> bool {
+        if self.fifo.startup > 0 {
+            self.fifo.startup -= 1;
+            return false;
+        }
+
+        if let Some((index, dots)) = self.fifo.object_fetch {
+            if dots > 1 {
+                self.fifo.object_fetch = Some((index, dots - 1));
+            } else {
+                self.merge_object(index);
+                self.fifo.object_fetch = None;
+            }
+            return false;
+        }
+
+        // An object starting at this column stalls the FIFO while its row is fetched. The first
+        // object on a tile also waits for the background fetcher, longer the further left in
+        // the tile it is.
+        if self.fifo.discard == 0 && self.lcdc & OBJ_ENABLE != 0 {
+            if let Some(&index) = self.line_objects.get(self.fifo.fetched_objects) {
+                if self.oam[index * 4 + 1] as usize <= self.fifo.x + 8 {
+                    let mut wait = 0;
+                    if !self.fifo.tile_stalled {
+                        let offset = match self.fifo.bg.len() {
+                            0 => 0,
+                            len => 8 - len.min(8),
+                        };
+                        wait = 5 - offset.min(5) as u8;
+                        self.fifo.tile_stalled = true;
+                    }
+                    self.fifo.fetched_objects += 1;
+                    self.fifo.object_fetch = Some((index, wait + OBJECT_FETCH_DOTS - 1));
+                    return false;
+                }
+            }
+        }
+
+        // Reaching WX restarts the fetcher on the window's tile map.
+        if !self.fifo.window
+            && self.lcdc & WINDOW_ENABLE != 0
+            && self.window_reached
+            && self.fifo.x + 7 >= self.wx as usize
+        {
+            self.fifo.window = true;
+            self.fifo.window_drawn = true;
+            self.fifo.bg.clear();
+            self.fifo.restart_fetcher();
+            // With WX below 7, the window's leftmost pixels are off screen.
+            self.fifo.discard = (7 - self.wx.min(7)).saturating_sub(self.fifo.x as u8);
+        }
+
+        self.fetcher_dot();
+
+        let Some(bg_color) = self.fifo.bg.pop_front() else {
+            return false;
+        };
+        let object = self.fifo.objects.pop_front();
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return false;
+        }
+
+        // On DMG, clearing LCDC bit 0 blanks both the background and the window.
+        let bg_color = if self.lcdc & BG_ENABLE != 0 {
+            bg_color
+        } else {
+            0
+        };
+        let pixel = match object {
+            Some(object)
+                if object.color != 0
+                    && self.lcdc & OBJ_ENABLE != 0
+                    && !(object.behind_bg && bg_color != 0) =>
+            {
+                if object.obp1 {
+                    OBP1_PIXEL | ((self.obp1 >> (object.color * 2)) & 0x03)
+                } else {
+                    OBP0_PIXEL | ((self.obp0 >> (object.color * 2)) & 0x03)
+                }
+            }
+            _ => (self.bgp >> (bg_color * 2)) & 0x03,
+        };
+        self.buffer[self.ly as usize * SCREEN_WIDTH + self.fifo.x] = pixel;
+        self.fifo.x += 1;
+
+        if self.fifo.x == SCREEN_WIDTH {
+            if self.fifo.window_drawn {
+                self.window_line += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    // Each of the three reads takes two dots. The row is pushed once the FIFO has run dry.
+    fn fetcher_dot(&mut self) {
+        let fifo = &mut self.fifo;
+        if fifo.step != Step::Push {
+            fifo.step_dots += 1;
+            if fifo.step_dots < 2 {
+                return;
+            }
+            fifo.step_dots = 0;
+        }
+        match self.fifo.step {
+            Step::Tile => {
+                let (map_select, x, y) = self.fetcher_position();
+                let map = if self.lcdc & map_select != 0 {
+                    0x1c00
+                } else {
+                    0x1800
+                };
+                self.fifo.tile = self.vram[map + (y as usize / 8) * 32 + x as usize];
+                self.fifo.step = Step::DataLow;
+            }
+            Step::DataLow => {
+                let addr = self.fetcher_row_addr();
+                self.fifo.data_low = self.vram[addr];
+                self.fifo.step = Step::DataHigh;
+            }
+            Step::DataHigh => {
+                let addr = self.fetcher_row_addr();
+                self.fifo.data_high = self.vram[addr + 1];
+                self.fifo.step = Step::Push;
+            }
+            Step::Push => {
+                if self.fifo.bg.is_empty() {
+                    for bit in (0..8).rev() {
+                        let color = ((self.fifo.data_high >> bit) & 1) << 1
+                            | ((self.fifo.data_low >> bit) & 1);
+                        self.fifo.bg.push_back(color);
+                    }
+                    self.fifo.tile_x = self.fifo.tile_x.wrapping_add(1);
+                    self.fifo.step = Step::Tile;
+                    self.fifo.tile_stalled = false;
+                }
+            }
+        }
+    }
+
+    // The tile map, tile column and pixel row the fetcher is reading. SCY is read on every
+    // fetch, so changing it mid-line takes effect at the next tile.
+    fn fetcher_position(&self) -> (u8, u8, u8) {
+        if self.fifo.window {
+            (WINDOW_TILE_MAP, self.fifo.tile_x & 31, self.window_line)
+        } else {
+            let x = (self.scx / 8).wrapping_add(self.fifo.tile_x) & 31;
+            (BG_TILE_MAP, x, self.scy.wrapping_add(self.ly))
+        }
+    }
+
+    fn fetcher_row_addr(&self) -> usize {
+        let (_, _, y) = self.fetcher_position();
+        self.tile_data_addr(self.fifo.tile) + (y as usize % 8) * 2
+    }
+
+    // Objects already in the FIFO keep the pixels they cover, which gives the one with the
+    // smaller X (or, on a tie, the lower OAM index) priority.
+    fn merge_object(&mut self, index: usize) {
+        let object = &self.oam[index * 4..index * 4 + 4];
+        let (y, x, tile, attributes) = (object[0], object[1], object[2], object[3]);
+        let height = self.object_height();
+        let mut row = self.ly.wrapping_add(16).wrapping_sub(y);
+        if attributes & OBJ_FLIP_Y != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 { tile & 0xfe } else { tile };
+        let addr = tile as usize * 16 + row as usize * 2;
+        let (low, high) = (self.vram[addr], self.vram[addr + 1]);
+
+        while self.fifo.objects.len() < 8 {
+            self.fifo.objects.push_back(ObjectPixel::default());
+        }
+        // Objects partly off the left edge lose the columns left of the screen.
+        let hidden = (self.fifo.x + 8).saturating_sub(x as usize);
+        for column in hidden..8 {
+            let bit = if attributes & OBJ_FLIP_X != 0 {
+                column
+            } else {
+                7 - column
+            };
+            let color = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+            let slot = &mut self.fifo.objects[column - hidden];
+            if slot.color == 0 {
+                *slot = ObjectPixel {
+                    color,
+                    obp1: attributes & OBJ_PALETTE != 0,
+                    behind_bg: attributes & OBJ_BEHIND_BG != 0,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Mode, Renderer, OBJ_ENABLE, WINDOW_ENABLE};
+    use super::*;
+    use crate::context::Context;
+    use crate::model::Model;
+
+    // Runs line 0 and returns how many dots mode 3 took.
+    fn mode_3_dots(setup: impl FnOnce(&mut Ppu)) -> usize {
+        let mut ppu = Ppu::after_boot(Model::Dmg, Renderer::Fifo);
+        setup(&mut ppu);
+        let mut ctx = Context::default();
+        let mut dots = 0;
+        while ppu.mode != Mode::HBlank {
+            ppu.tick(&mut ctx);
+            if ppu.mode == Mode::Drawing {
+                dots += 1;
+            }
+        }
+        dots
+    }
+
+    #[test]
+    fn fine_scrolling_adds_scx_mod_8_dots() {
+        assert_eq!(mode_3_dots(|_| {}), 172);
+        for scx in [3, 11, 255] {
+            assert_eq!(mode_3_dots(|ppu| ppu.scx = scx), 172 + scx as usize % 8);
+        }
+    }
+
+    #[test]
+    fn starting_the_window_adds_6_dots() {
+        for wx in [8, 100, 166] {
+            let dots = mode_3_dots(|ppu| {
+                ppu.lcdc |= WINDOW_ENABLE;
+                ppu.window_reached = true;
+                ppu.wx = wx;
+            });
+            assert_eq!(dots, 178, "WX={}", wx);
+        }
+        // The window only starts once LY has reached WY.
+        let dots = mode_3_dots(|ppu| {
+            ppu.lcdc |= WINDOW_ENABLE;
+            ppu.wx = 100;
+        });
+        assert_eq!(dots, 172);
+    }
+
+    #[test]
+    fn objects_add_6_to_11_dots() {
+        let with_objects = |xs: &'static [u8]| {
+            move |ppu: &mut Ppu| {
+                ppu.lcdc |= OBJ_ENABLE;
+                for (index, &x) in xs.iter().enumerate() {
+                    ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[16, x, 0, 0]);
+                }
+                // `scan_oam` will run at the end of mode 2.
+            }
+        };
+        // The wait for the background fetcher shrinks the further right in its tile the
+        // object starts.
+        assert_eq!(mode_3_dots(with_objects(&[0])), 183);
+        assert_eq!(mode_3_dots(with_objects(&[8])), 183);
+        assert_eq!(mode_3_dots(with_objects(&[9])), 182);
+        assert_eq!(mode_3_dots(with_objects(&[13])), 178);
+        assert_eq!(mode_3_dots(with_objects(&[100])), 179);
+        // Only the first object on a tile waits for the fetcher.
+        assert_eq!(mode_3_dots(with_objects(&[8, 8])), 189);
+        // Objects past the right edge cost nothing.
+        assert_eq!(mode_3_dots(with_objects(&[168])), 172);
+    }
+}
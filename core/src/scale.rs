@@ -0,0 +1,407 @@
+use wasm_bindgen::prelude::*;
+
+// Pixel-art upscalers, run on the CPU for frontends without shaders.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaler {
+    Nearest2x,
+    Nearest3x,
+    Nearest4x,
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    Xbr2x,
+}
+
+impl Scaler {
+    pub fn factor(self) -> usize {
+        match self {
+            Scaler::Nearest2x | Scaler::Scale2x | Scaler::Hq2x | Scaler::Xbr2x => 2,
+            Scaler::Nearest3x | Scaler::Scale3x => 3,
+            Scaler::Nearest4x => 4,
+        }
+    }
+}
+
+// Upscales `src`, RGBA pixels in rows of `width`, into `dst`, which is resized to fit.
+pub fn upscale(scaler: Scaler, src: &[u8], width: usize, dst: &mut Vec<u8>) {
+    let pixels: Vec<u32> = src
+        .chunks_exact(4)
+        .map(|p| u32::from_ne_bytes([p[0], p[1], p[2], p[3]]))
+        .collect();
+    let image = Image {
+        pixels: &pixels,
+        width,
+        height: pixels.len() / width,
+    };
+    let factor = scaler.factor();
+    let mut out = Output {
+        data: dst,
+        width: width * factor,
+    };
+    out.data.resize(src.len() * factor * factor, 0);
+    for y in 0..image.height {
+        for x in 0..width {
+            let block = Block {
+                image: &image,
+                x: x as isize,
+                y: y as isize,
+            };
+            match scaler {
+                Scaler::Nearest2x | Scaler::Nearest3x | Scaler::Nearest4x => {
+                    let p = block.at(0, 0);
+                    for dy in 0..factor {
+                        for dx in 0..factor {
+                            out.put(x * factor + dx, y * factor + dy, p);
+                        }
+                    }
+                }
+                Scaler::Scale2x => {
+                    for (sx, sy) in CORNERS {
+                        let p = scale2x_corner(&block, sx, sy);
+                        out.put(corner_x(x, 2, sx), corner_y(y, 2, sy), p);
+                    }
+                }
+                Scaler::Scale3x => scale3x(&block, &mut out, x, y),
+                Scaler::Hq2x => {
+                    for (sx, sy) in CORNERS {
+                        let p = hq2x_corner(&block, sx, sy);
+                        out.put(corner_x(x, 2, sx), corner_y(y, 2, sy), p);
+                    }
+                }
+                Scaler::Xbr2x => {
+                    for (sx, sy) in CORNERS {
+                        let p = xbr_corner(&block, sx, sy);
+                        out.put(corner_x(x, 2, sx), corner_y(y, 2, sy), p);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Each corner is worked out in a frame mirrored so that it points towards (sx, sy).
+const CORNERS: [(isize, isize); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+fn corner_x(x: usize, factor: usize, sx: isize) -> usize {
+    if sx < 0 {
+        x * factor
+    } else {
+        x * factor + factor - 1
+    }
+}
+
+fn corner_y(y: usize, factor: usize, sy: isize) -> usize {
+    corner_x(y, factor, sy)
+}
+
+struct Image<'a> {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize,
+}
+
+// A source pixel and its neighbourhood. Pixels past the edges repeat the edge.
+struct Block<'a> {
+    image: &'a Image<'a>,
+    x: isize,
+    y: isize,
+}
+
+impl Block<'_> {
+    fn at(&self, dx: isize, dy: isize) -> u32 {
+        let x = (self.x + dx).clamp(0, self.image.width as isize - 1) as usize;
+        let y = (self.y + dy).clamp(0, self.image.height as isize - 1) as usize;
+        self.image.pixels[y * self.image.width + x]
+    }
+}
+
+struct Output<'a> {
+    data: &'a mut Vec<u8>,
+    width: usize,
+}
+
+impl Output<'_> {
+    fn put(&mut self, x: usize, y: usize, pixel: u32) {
+        let i = (y * self.width + x) * 4;
+        self.data[i..i + 4].copy_from_slice(&pixel.to_ne_bytes());
+    }
+}
+
+// Weighted average of each channel.
+fn mix<const N: usize>(parts: [(u32, u32); N]) -> u32 {
+    let total: u32 = parts.iter().map(|&(_, weight)| weight).sum();
+    let mut channels = [0u32; 4];
+    for (pixel, weight) in parts {
+        for (channel, byte) in channels.iter_mut().zip(pixel.to_ne_bytes()) {
+            *channel += byte as u32 * weight;
+        }
+    }
+    u32::from_ne_bytes(channels.map(|channel| ((channel + total / 2) / total) as u8))
+}
+
+fn yuv(pixel: u32) -> (i32, i32, i32) {
+    let [r, g, b, _] = pixel.to_ne_bytes().map(|c| c as i32);
+    let y = (299 * r + 587 * g + 114 * b) / 1000;
+    let u = (-169 * r - 331 * g + 500 * b) / 1000;
+    let v = (500 * r - 419 * g - 81 * b) / 1000;
+    (y, u, v)
+}
+
+// The thresholds hqx uses to tell colors apart.
+fn similar(a: u32, b: u32) -> bool {
+    let ((ya, ua, va), (yb, ub, vb)) = (yuv(a), yuv(b));
+    (ya - yb).abs() <= 48 && (ua - ub).abs() <= 7 && (va - vb).abs() <= 6
+}
+
+// xBR's color distance.
+fn distance(a: u32, b: u32) -> i32 {
+    let ((ya, ua, va), (yb, ub, vb)) = (yuv(a), yuv(b));
+    48 * (ya - yb).abs() + 7 * (ua - ub).abs() + 6 * (va - vb).abs()
+}
+
+// https://www.scale2x.it/algorithm
+// A corner takes the color of its two neighbours when they match and the opposite ones do not.
+fn scale2x_corner(block: &Block, sx: isize, sy: isize) -> u32 {
+    let p = block.at(0, 0);
+    let (h, v) = (block.at(sx, 0), block.at(0, sy));
+    let (opposite_h, opposite_v) = (block.at(-sx, 0), block.at(0, -sy));
+    if h == v && h != opposite_v && v != opposite_h {
+        v
+    } else {
+        p
+    }
+}
+
+fn scale3x(block: &Block, out: &mut Output, x: usize, y: usize) {
+    let [a, b, c] = [block.at(-1, -1), block.at(0, -1), block.at(1, -1)];
+    let [d, e, f] = [block.at(-1, 0), block.at(0, 0), block.at(1, 0)];
+    let [g, h, i] = [block.at(-1, 1), block.at(0, 1), block.at(1, 1)];
+    let mut result = [e; 9];
+    if b != h && d != f {
+        result[0] = if d == b { d } else { e };
+        result[1] = if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        };
+        result[2] = if b == f { f } else { e };
+        result[3] = if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        };
+        result[5] = if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        };
+        result[6] = if d == h { d } else { e };
+        result[7] = if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        };
+        result[8] = if h == f { f } else { e };
+    }
+    for (k, &pixel) in result.iter().enumerate() {
+        out.put(x * 3 + k % 3, y * 3 + k / 3, pixel);
+    }
+}
+
+// https://en.wikipedia.org/wiki/Hqx
+// The neighbours that differ from the center form an 8-bit pattern, and hq2x's table picks the
+// blend for each of the 256 patterns. The table is written for the top-left corner; the other
+// corners rotate the neighbourhood so that they point up and to the left as well.
+fn hq2x_corner(block: &Block, sx: isize, sy: isize) -> u32 {
+    let at = |dx: isize, dy: isize| match (sx, sy) {
+        (-1, -1) => block.at(dx, dy),
+        (1, -1) => block.at(-dy, dx),
+        (1, 1) => block.at(-dx, -dy),
+        _ => block.at(dy, -dx),
+    };
+    let e = at(0, 0);
+    let neighbours = [
+        at(-1, -1),
+        at(0, -1),
+        at(1, -1),
+        at(-1, 0),
+        at(1, 0),
+        at(-1, 1),
+        at(0, 1),
+        at(1, 1),
+    ];
+    let pattern = (0..8)
+        .filter(|&i| !similar(e, neighbours[i]))
+        .fold(0, |pattern, i| pattern | 1 << i);
+    let [a, b, _, d, f, _, h, _] = neighbours;
+    match HQ2X[pattern] {
+        0 => e,
+        1 => blend([(e, 3), (a, 1)]),
+        2 => blend([(e, 3), (d, 1)]),
+        3 => blend([(e, 3), (b, 1)]),
+        4 => blend([(e, 2), (d, 1), (b, 1)]),
+        5 => blend([(e, 2), (a, 1), (b, 1)]),
+        6 => blend([(e, 2), (a, 1), (d, 1)]),
+        7 => blend([(e, 5), (b, 2), (d, 1)]),
+        8 => blend([(e, 5), (d, 2), (b, 1)]),
+        9 => blend([(e, 2), (d, 3), (b, 3)]),
+        10 => blend([(e, 6), (d, 1), (b, 1)]),
+        11 => blend([(e, 14), (d, 1), (b, 1)]),
+        // The rest only blend across the corner when the edge runs along it.
+        12 if similar(b, d) => blend([(e, 2), (d, 1), (b, 1)]),
+        13 if similar(b, d) => blend([(e, 6), (d, 1), (b, 1)]),
+        14 if similar(b, d) => blend([(e, 14), (d, 1), (b, 1)]),
+        12..=14 => e,
+        15 if similar(b, d) => blend([(e, 2), (d, 1), (b, 1)]),
+        16 if similar(b, d) => blend([(e, 2), (d, 3), (b, 3)]),
+        17 if similar(b, d) => blend([(e, 6), (d, 1), (b, 1)]),
+        15..=17 => blend([(e, 3), (a, 1)]),
+        18 if similar(b, f) => blend([(e, 5), (b, 2), (d, 1)]),
+        18 => blend([(e, 3), (d, 1)]),
+        19 if similar(d, h) => blend([(e, 5), (d, 2), (b, 1)]),
+        _ => blend([(e, 3), (b, 1)]),
+    }
+}
+
+// hqx's interpolation: weights summing to a power of two, rounded down.
+fn blend<const N: usize>(parts: [(u32, u32); N]) -> u32 {
+    let total: u32 = parts.iter().map(|&(_, weight)| weight).sum();
+    let mut channels = [0u32; 4];
+    for (pixel, weight) in parts {
+        for (channel, byte) in channels.iter_mut().zip(pixel.to_ne_bytes()) {
+            *channel += byte as u32 * weight;
+        }
+    }
+    u32::from_ne_bytes(channels.map(|channel| (channel / total) as u8))
+}
+
+// The blend for the top-left corner, indexed by the pattern of differing neighbours: bit 0 for
+// the top-left one, then top, top-right, left, right, bottom-left, bottom and bottom-right.
+#[rustfmt::skip]
+const HQ2X: [u8; 256] = [
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 12, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 16, 14,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19, 12, 12, 5, 19, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19,  1, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6, 18, 5,  3, 16, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 16, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 13, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 13,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3,  1, 12, 5,  3,  1, 14,
+];
+
+// https://forums.libretro.com/t/xbr-algorithm-tutorial/123
+// Level 1 xBR: compares the color gradients along the two diagonals through the corner, and
+// when the edge runs across it, blends the corner halfway towards the closer neighbour.
+fn xbr_corner(block: &Block, sx: isize, sy: isize) -> u32 {
+    let at = |dx: isize, dy: isize| block.at(dx * sx, dy * sy);
+    let e = at(0, 0);
+    let (b, c, d, f) = (at(0, -1), at(1, -1), at(-1, 0), at(1, 0));
+    let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+    let (f4, i4, h5, i5) = (at(2, 0), at(2, 1), at(0, 2), at(1, 2));
+    let along_ei =
+        distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4 * distance(h, f);
+    let along_hf =
+        distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4 * distance(e, i);
+    if along_ei < along_hf && e != f && e != h {
+        let closer = if distance(e, f) <= distance(e, h) {
+            f
+        } else {
+            h
+        };
+        mix([(e, 1), (closer, 1)])
+    } else {
+        e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Scaler; 7] = [
+        Scaler::Nearest2x,
+        Scaler::Nearest3x,
+        Scaler::Nearest4x,
+        Scaler::Scale2x,
+        Scaler::Scale3x,
+        Scaler::Hq2x,
+        Scaler::Xbr2x,
+    ];
+
+    fn image(pixels: &[u32]) -> Vec<u8> {
+        pixels.iter().flat_map(|p| p.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let src = image(&[0xff336699; 12]);
+        for scaler in ALL {
+            let mut dst = Vec::new();
+            upscale(scaler, &src, 4, &mut dst);
+            assert_eq!(dst.len(), src.len() * scaler.factor() * scaler.factor());
+            assert!(dst.chunks_exact(4).all(|p| p == &src[..4]), "{:?}", scaler);
+        }
+    }
+
+    #[test]
+    fn scale2x_rounds_diagonal_corners() {
+        const W: u32 = 0xffffffff;
+        const B: u32 = 0xff000000;
+        #[rustfmt::skip]
+        let src = image(&[
+            W, B, W,
+            B, W, W,
+            W, W, W,
+        ]);
+        let mut dst = Vec::new();
+        upscale(Scaler::Scale2x, &src, 3, &mut dst);
+        let pixel = |x: usize, y: usize| {
+            u32::from_ne_bytes(dst[(y * 6 + x) * 4..][..4].try_into().unwrap())
+        };
+        // The white center takes the black of its top-left neighbours in that corner only.
+        assert_eq!(pixel(2, 2), B);
+        assert_eq!([pixel(3, 2), pixel(2, 3), pixel(3, 3)], [W; 3]);
+    }
+
+    #[test]
+    fn hq2x_keeps_straight_edges_sharp() {
+        const W: u32 = 0xffffffff;
+        const B: u32 = 0xff000000;
+        let src = image(&[B, B, B, W, W, W, W, W, W]);
+        let mut dst = Vec::new();
+        upscale(Scaler::Hq2x, &src, 3, &mut dst);
+        let mut nearest = Vec::new();
+        upscale(Scaler::Nearest2x, &src, 3, &mut nearest);
+        assert_eq!(dst, nearest);
+    }
+
+    #[test]
+    fn hq2x_softens_isolated_pixels() {
+        const W: u32 = 0xffffffff;
+        const B: u32 = 0xff000000;
+        #[rustfmt::skip]
+        let src = image(&[
+            W, W, W,
+            W, B, W,
+            W, W, W,
+        ]);
+        let mut dst = Vec::new();
+        upscale(Scaler::Hq2x, &src, 3, &mut dst);
+        let pixel = |x: usize, y: usize| {
+            u32::from_ne_bytes(dst[(y * 6 + x) * 4..][..4].try_into().unwrap())
+        };
+        // Each corner takes 1/16 from both of its white neighbours, rounded down.
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            assert_eq!(pixel(x, y), 0xff1f1f1f);
+        }
+    }
+}
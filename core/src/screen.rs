@@ -0,0 +1,164 @@
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::scale::{self, Scaler};
+use wasm_bindgen::prelude::*;
+
+// Colors for the four DMG shades as 0xRRGGBB, lightest first.
+const GREEN: [u32; 4] = [0x9bbc0f, 0x8bac0f, 0x306230, 0x0f380f];
+const POCKET: [u32; 4] = [0xffffff, 0xaaaaaa, 0x555555, 0x000000];
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PalettePreset {
+    // The yellow-green of the original Game Boy screen.
+    Green,
+    // The neutral grays of the Game Boy Pocket.
+    #[default]
+    Pocket,
+}
+
+impl PalettePreset {
+    fn colors(self) -> [u32; 4] {
+        match self {
+            PalettePreset::Green => GREEN,
+            PalettePreset::Pocket => POCKET,
+        }
+    }
+}
+
+// The CGB and AGB screens wash out and shift colors. Correcting for them makes colors chosen
+// for those screens look as intended on a modern display.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorCorrection {
+    #[default]
+    None,
+    Cgb,
+    Agb,
+}
+
+// Turns the PPU's frame into RGBA pixels.
+pub struct Screen {
+    palettes: [[u32; 4]; 3], // BGP, OBP0 and OBP1
+    correction: ColorCorrection,
+    colors: [[u8; 4]; 12], // RGBA for each frame pixel value
+    // The DMG screen is slow to change, so a pixel keeps part of its previous color. `decay`
+    // is the share kept each frame, and `blended` holds the unrounded RGB so that trails fade
+    // out completely.
+    decay: f32,
+    blended: Vec<f32>,
+    pub frame: Vec<u8>,
+    // `frame` enlarged by the last `upscale`, `scale` times in each direction. Empty until then.
+    pub scaled: Vec<u8>,
+    pub scale: usize,
+}
+
+impl Default for Screen {
+    fn default() -> Screen {
+        let mut screen = Screen {
+            palettes: [POCKET; 3],
+            correction: ColorCorrection::None,
+            colors: [[0; 4]; 12],
+            decay: 0.0,
+            blended: vec![0.0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+            scaled: Vec::new(),
+            scale: 0,
+        };
+        screen.update_colors();
+        screen.refresh(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        screen
+    }
+}
+
+impl Screen {
+    pub fn set_preset(&mut self, preset: PalettePreset) {
+        self.palettes = [preset.colors(); 3];
+        self.update_colors();
+    }
+
+    pub fn set_palettes(&mut self, palettes: [[u32; 4]; 3]) {
+        self.palettes = palettes;
+        self.update_colors();
+    }
+
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.correction = correction;
+        self.update_colors();
+    }
+
+    // Only 12 colors can appear, so correction is done once per palette change rather than
+    // once per pixel.
+    fn update_colors(&mut self) {
+        for (i, color) in self.colors.iter_mut().enumerate() {
+            let rgb = self.palettes[i / 4][i % 4];
+            *color = correct(rgb, self.correction);
+        }
+    }
+
+    // 0 turns blending off.
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay;
+    }
+
+    // Shows a new frame from the PPU.
+    pub fn present(&mut self, pixels: &[u8]) {
+        if self.decay == 0.0 {
+            self.refresh(pixels);
+            return;
+        }
+        let pixels = self.frame.chunks_exact_mut(4).zip(pixels);
+        for ((out, &pixel), blended) in pixels.zip(self.blended.chunks_exact_mut(3)) {
+            let color = self.colors[pixel as usize];
+            for channel in 0..3 {
+                let value = &mut blended[channel];
+                *value = color[channel] as f32 * (1.0 - self.decay) + *value * self.decay;
+                out[channel] = value.round() as u8;
+            }
+        }
+    }
+
+    // Shows a frame without blending it into the previous one, e.g. to recolor the frame on
+    // screen after a palette change.
+    pub fn refresh(&mut self, pixels: &[u8]) {
+        let pixels = self.frame.chunks_exact_mut(4).zip(pixels);
+        for ((out, &pixel), blended) in pixels.zip(self.blended.chunks_exact_mut(3)) {
+            let color = self.colors[pixel as usize];
+            out.copy_from_slice(&color);
+            for (blended, &channel) in blended.iter_mut().zip(&color) {
+                *blended = channel as f32;
+            }
+        }
+    }
+
+    pub fn upscale(&mut self, scaler: Scaler) {
+        scale::upscale(scaler, &self.frame, SCREEN_WIDTH, &mut self.scaled);
+        self.scale = scaler.factor();
+    }
+}
+
+fn correct(rgb: u32, correction: ColorCorrection) -> [u8; 4] {
+    let [_, r, g, b] = rgb.to_be_bytes();
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let (r, g, b) = match correction {
+        ColorCorrection::None => (r, g, b),
+        // Channel mixing after byuu's CGB filter.
+        ColorCorrection::Cgb => (
+            (26.0 * r + 4.0 * g + 2.0 * b) / 32.0,
+            (24.0 * g + 8.0 * b) / 32.0,
+            (6.0 * r + 4.0 * g + 22.0 * b) / 32.0,
+        ),
+        // Talarubi's AGB filter: undo the screen's gamma of about 4, mix, then encode for a
+        // display gamma of 2.2.
+        ColorCorrection::Agb => {
+            let (r, g, b) = (r.powf(4.0), g.powf(4.0), b.powf(4.0));
+            let encode = |c: f32| (c / 255.0).powf(1.0 / 2.2) * 255.0 / 280.0;
+            (
+                encode(255.0 * r + 50.0 * g),
+                encode(10.0 * r + 230.0 * g + 30.0 * b),
+                encode(50.0 * r + 10.0 * g + 220.0 * b),
+            )
+        }
+    };
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(r), channel(g), channel(b), 0xff]
+}
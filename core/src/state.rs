@@ -0,0 +1,101 @@
+use crate::cartridge::CartridgeError;
+
+// Little-endian encoding for cartridge save states. Fields are read back in the order they
+// were written, so a state only loads into the mapper type that produced it.
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Length-prefixed, so that a state saved with a different memory size is rejected.
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CartridgeError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(CartridgeError::InvalidState)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, CartridgeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, CartridgeError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, CartridgeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, CartridgeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, CartridgeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Fills `value`, which must have the length the state was saved with.
+    pub fn bytes(&mut self, value: &mut [u8]) -> Result<(), CartridgeError> {
+        if self.u32()? as usize != value.len() {
+            return Err(CartridgeError::InvalidState);
+        }
+        value.copy_from_slice(self.take(value.len())?);
+        Ok(())
+    }
+
+    // Fails if the state has data left over.
+    pub fn finish(self) -> Result<(), CartridgeError> {
+        if self.pos != self.data.len() {
+            return Err(CartridgeError::InvalidState);
+        }
+        Ok(())
+    }
+}